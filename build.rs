@@ -0,0 +1,27 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    #[cfg(feature = "ffi")]
+    generate_header();
+}
+
+/// Writes the C header for the `ffi` module to `OUT_DIR`, and also to the committed
+/// `include/idsp.h` if `IDSP_UPDATE_HEADER` is set.
+#[cfg(feature = "ffi")]
+fn generate_header() {
+    use std::{env, path::Path};
+
+    let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out_dir = env::var("OUT_DIR").unwrap();
+
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-env-changed=IDSP_UPDATE_HEADER");
+
+    let bindings = cbindgen::generate(&crate_dir).expect("Unable to generate the C header");
+    bindings.write_to_file(Path::new(&out_dir).join("idsp.h"));
+
+    if env::var_os("IDSP_UPDATE_HEADER").is_some() {
+        bindings.write_to_file(Path::new(&crate_dir).join("include/idsp.h"));
+    }
+}
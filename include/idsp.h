@@ -0,0 +1,123 @@
+#ifndef IDSP_H
+#define IDSP_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef enum IdspStatus {
+  IDSP_STATUS_OK,
+  IDSP_STATUS_NULL_POINTER,
+  IDSP_STATUS_IO,
+  IDSP_STATUS_INVALID_HEADER,
+  IDSP_STATUS_INVALID_AUDIO_LENGTH,
+  IDSP_STATUS_UNSUPPORTED_FORMAT,
+  IDSP_STATUS_INVALID_CHANNEL_COUNT,
+  IDSP_STATUS_INVALID_LOOP_POINTS,
+  IDSP_STATUS_INVALID_BLOCK_SIZE,
+  IDSP_STATUS_MISMATCHED_CHANNELS,
+  // The channel index is out of range.
+  IDSP_STATUS_INVALID_CHANNEL,
+  // The caller's buffer can't hold the result.
+  IDSP_STATUS_BUFFER_TOO_SMALL,
+  IDSP_STATUS_INVALID_HEADER_SIZE,
+  IDSP_STATUS_INVALID_SAMPLE_RATE,
+  IDSP_STATUS_INVALID_INDEX,
+  // A bug in this library, caught before it could unwind into the caller.
+  IDSP_STATUS_PANIC,
+} IdspStatus;
+
+typedef struct IdspContainer IdspContainer;
+
+// Bytes allocated by this library.
+typedef struct IdspBuffer {
+  uint8_t *data;
+  size_t len;
+} IdspBuffer;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Reads the IDSP file in `bytes[..len]`, storing the container in `out` on success.
+//
+// # Safety
+//
+// `bytes` must point to `len` readable bytes and `out` must be valid for writes.
+enum IdspStatus idsp_open(const uint8_t *bytes, size_t len, struct IdspContainer **out);
+
+// Frees a container from [`idsp_open`]. Null is ignored.
+//
+// # Safety
+//
+// `container` must come from [`idsp_open`] and not have been freed already.
+void idsp_free(struct IdspContainer *container);
+
+// # Safety
+//
+// `container` must be a live container from [`idsp_open`].
+size_t idsp_channel_count(const struct IdspContainer *container);
+
+// # Safety
+//
+// `container` must be a live container from [`idsp_open`].
+size_t idsp_sample_count(const struct IdspContainer *container);
+
+// # Safety
+//
+// `container` must be a live container from [`idsp_open`].
+uint32_t idsp_sample_rate(const struct IdspContainer *container);
+
+// Returns whether the audio loops, storing the loop points in `start` and `end`, with `end`
+// exclusive, if it does.
+//
+// # Safety
+//
+// `container` must be a live container from [`idsp_open`], and `start` and `end` must be
+// valid for writes.
+bool idsp_loop_points(const struct IdspContainer *container, size_t *start, size_t *end);
+
+// Decodes `channel` into `pcm`, which has to hold at least [`idsp_sample_count`] samples.
+//
+// # Safety
+//
+// `container` must be a live container from [`idsp_open`] and `pcm` must point to `pcm_len`
+// writable samples.
+enum IdspStatus idsp_decode_channel(const struct IdspContainer *container,
+                                    size_t channel,
+                                    int16_t *pcm,
+                                    size_t pcm_len);
+
+// Encodes `sample_count` frames of interleaved PCM with `channel_count` channels into an
+// IDSP file, storing it in `out` on success. `loop_end` is exclusive and both loop points
+// are ignored unless `looping` is set.
+//
+// # Safety
+//
+// `pcm` must point to `sample_count * channel_count` readable samples and `out` must be
+// valid for writes.
+enum IdspStatus idsp_encode(const int16_t *pcm,
+                            size_t sample_count,
+                            size_t channel_count,
+                            uint32_t sample_rate,
+                            bool looping,
+                            size_t loop_start,
+                            size_t loop_end,
+                            struct IdspBuffer *out);
+
+// Frees a buffer from [`idsp_encode`]. Empty buffers are ignored.
+//
+// # Safety
+//
+// `buffer` must come from [`idsp_encode`] and not have been freed already.
+void idsp_buffer_free(struct IdspBuffer buffer);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* IDSP_H */
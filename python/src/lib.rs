@@ -0,0 +1,295 @@
+//! Python bindings, built into an `idsp` module with maturin.
+//!
+//! PCM is taken from anything exposing an `int16` buffer, such as `array.array("h")` or a
+//! numpy array, and returned as `array.array("h")`, which numpy wraps without copying through
+//! `numpy.asarray`.
+
+use idsp::{
+    decode::decode_gc_adpcm,
+    encode::encode_gc_adpcm,
+    idsp::{read_idsp, read_idsp_bytes, write_idsp_bytes, Channel, DecodeError, EncodeError},
+};
+use pyo3::{
+    buffer::PyBuffer,
+    exceptions::{PyIOError, PyValueError},
+    prelude::*,
+    types::{PyBytes, PyList},
+};
+
+fn decode_error(err: DecodeError) -> PyErr {
+    match err {
+        DecodeError::Io(err) => PyIOError::new_err(err.to_string()),
+        err => PyValueError::new_err(format!("{:?}", err)),
+    }
+}
+
+fn encode_error(err: EncodeError) -> PyErr {
+    PyValueError::new_err(format!("{:?}", err))
+}
+
+/// Reads PCM from an `int16` buffer, falling back to any sequence of ints.
+fn pcm_from(pcm: &Bound<'_, PyAny>) -> PyResult<Vec<i16>> {
+    match PyBuffer::<i16>::get(pcm) {
+        Ok(buffer) => buffer.to_vec(pcm.py()),
+        Err(_) => pcm.extract(),
+    }
+}
+
+fn pcm_to_array<'py>(py: Python<'py>, pcm: &[i16]) -> PyResult<Bound<'py, PyAny>> {
+    let bytes: Vec<u8> = pcm.iter().flat_map(|sample| sample.to_ne_bytes()).collect();
+    py.import("array")?.getattr("array")?.call1(("h", PyBytes::new(py, &bytes)))
+}
+
+#[pyclass(name = "Coefficients", module = "idsp")]
+#[derive(Clone)]
+struct PyCoefficients {
+    inner: idsp::Coefficients,
+}
+
+#[pymethods]
+impl PyCoefficients {
+    /// Calculates the coefficients best suited to encoding `pcm`.
+    #[staticmethod]
+    fn from_pcm(pcm: &Bound<'_, PyAny>) -> PyResult<Self> {
+        Ok(Self { inner: idsp::Coefficients::from(pcm_from(pcm)?) })
+    }
+
+    #[new]
+    fn new(coefs: [i16; 16]) -> Self {
+        Self { inner: idsp::Coefficients::new(coefs) }
+    }
+
+    #[getter]
+    fn coefs(&self) -> [i16; 16] {
+        self.inner.coefs
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Coefficients({:?})", self.inner.coefs)
+    }
+}
+
+#[pyclass(name = "Channel", module = "idsp")]
+#[derive(Clone)]
+struct PyChannel {
+    inner: Channel,
+}
+
+#[pymethods]
+impl PyChannel {
+    #[getter]
+    fn sample_count(&self) -> usize {
+        self.inner.metadata.sample_count
+    }
+
+    #[getter]
+    fn sample_rate(&self) -> usize {
+        self.inner.metadata.sample_rate
+    }
+
+    #[getter]
+    fn looping(&self) -> bool {
+        self.inner.metadata.looping
+    }
+
+    #[getter]
+    fn gain(&self) -> i16 {
+        self.inner.metadata.gain
+    }
+
+    #[getter]
+    fn coefficients(&self) -> PyCoefficients {
+        PyCoefficients { inner: self.inner.metadata.coefficients }
+    }
+
+    /// The encoded GC-ADPCM data.
+    #[getter]
+    fn audio<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.inner.audio)
+    }
+
+    fn decode<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        pcm_to_array(py, &self.inner.decode())
+    }
+}
+
+#[pyclass(name = "IdspContainer", module = "idsp")]
+#[derive(Clone)]
+struct PyIdspContainer {
+    inner: idsp::IdspContainer,
+}
+
+#[pymethods]
+impl PyIdspContainer {
+    #[getter]
+    fn looping(&self) -> bool {
+        self.inner.looping
+    }
+
+    #[getter]
+    fn channel_count(&self) -> usize {
+        self.inner.channel_count
+    }
+
+    #[getter]
+    fn sample_rate(&self) -> usize {
+        self.inner.sample_rate
+    }
+
+    #[getter]
+    fn loop_start(&self) -> usize {
+        self.inner.loop_start
+    }
+
+    #[getter]
+    fn loop_end(&self) -> usize {
+        self.inner.loop_end
+    }
+
+    #[getter]
+    fn sample_count(&self) -> usize {
+        self.inner.sample_count
+    }
+
+    #[getter]
+    fn interleave_size(&self) -> usize {
+        self.inner.interleave_size
+    }
+
+    #[getter]
+    fn header_size(&self) -> usize {
+        self.inner.header_size
+    }
+
+    #[getter]
+    fn channel_info_size(&self) -> usize {
+        self.inner.channel_info_size
+    }
+
+    #[getter]
+    fn channels<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyList>> {
+        let channels =
+            self.inner.channels.iter().map(|channel| PyChannel { inner: channel.clone() });
+        PyList::new(py, channels)
+    }
+
+    fn decode_channel<'py>(&self, py: Python<'py>, index: usize) -> PyResult<Bound<'py, PyAny>> {
+        match self.inner.channels.get(index) {
+            Some(channel) => pcm_to_array(py, &channel.decode()),
+            None => Err(PyValueError::new_err(format!("No channel {}", index))),
+        }
+    }
+
+    /// Writes the container out as an IDSP file.
+    fn to_bytes<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let bytes = write_idsp_bytes(&self.inner).map_err(encode_error)?;
+        Ok(PyBytes::new(py, &bytes))
+    }
+}
+
+/// Reads an IDSP file from a path, or from its contents if given `bytes`.
+#[pyfunction(name = "read_idsp")]
+fn py_read_idsp(source: &Bound<'_, PyAny>) -> PyResult<PyIdspContainer> {
+    let container = match source.downcast::<PyBytes>() {
+        Ok(bytes) => read_idsp_bytes(bytes.as_bytes()),
+        Err(_) => read_idsp(source.extract::<std::path::PathBuf>()?),
+    };
+
+    Ok(PyIdspContainer { inner: container.map_err(decode_error)? })
+}
+
+/// Encodes `pcm` into GC-ADPCM data.
+#[pyfunction(name = "encode_gc_adpcm")]
+fn py_encode_gc_adpcm<'py>(
+    py: Python<'py>,
+    pcm: &Bound<'py, PyAny>,
+    coefficients: &PyCoefficients,
+) -> PyResult<Bound<'py, PyBytes>> {
+    let encoded = encode_gc_adpcm(&pcm_from(pcm)?, &coefficients.inner);
+    Ok(PyBytes::new(py, &encoded.data))
+}
+
+/// Decodes GC-ADPCM data, as made by `encode_gc_adpcm`.
+#[pyfunction(name = "decode_gc_adpcm")]
+fn py_decode_gc_adpcm<'py>(
+    py: Python<'py>,
+    adpcm: &[u8],
+    coefficients: &PyCoefficients,
+) -> PyResult<Bound<'py, PyAny>> {
+    pcm_to_array(py, &decode_gc_adpcm(adpcm, &coefficients.inner))
+}
+
+#[pymodule]
+#[pyo3(name = "idsp")]
+fn idsp_module(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyCoefficients>()?;
+    module.add_class::<PyChannel>()?;
+    module.add_class::<PyIdspContainer>()?;
+    module.add_function(wrap_pyfunction!(py_read_idsp, module)?)?;
+    module.add_function(wrap_pyfunction!(py_encode_gc_adpcm, module)?)?;
+    module.add_function(wrap_pyfunction!(py_decode_gc_adpcm, module)?)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pyo3::{ffi::c_str, types::PyDict};
+
+    #[test]
+    fn test_module() {
+        Python::with_gil(|py| {
+            let module = PyModule::new(py, "idsp").unwrap();
+            idsp_module(&module).unwrap();
+
+            let locals = PyDict::new(py);
+            locals.set_item("idsp", module).unwrap();
+            locals
+                .set_item("path", concat!(env!("CARGO_MANIFEST_DIR"), "/../test_files/13.idsp"))
+                .unwrap();
+
+            py.run(
+                c_str!(
+                    r#"
+import array
+
+container = idsp.read_idsp(path)
+assert container.sample_count == 47035
+assert container.channel_count == 1
+assert not container.looping
+
+with open(path, "rb") as file:
+    assert idsp.read_idsp(file.read()).to_bytes() == container.to_bytes()
+
+pcm = container.decode_channel(0)
+assert isinstance(pcm, array.array) and pcm.typecode == "h"
+assert len(pcm) == 47035
+assert memoryview(pcm).format == "h"
+
+channel = container.channels[0]
+assert channel.decode() == pcm
+assert idsp.decode_gc_adpcm(channel.audio, channel.coefficients)[:47035] == pcm
+
+coefficients = idsp.Coefficients.from_pcm(pcm)
+assert len(coefficients.coefs) == 16
+assert idsp.Coefficients.from_pcm(list(pcm)).coefs == coefficients.coefs
+
+encoded = idsp.encode_gc_adpcm(pcm, coefficients)
+decoded = idsp.decode_gc_adpcm(encoded, coefficients)[:47035]
+assert sum(abs(a - b) for a, b in zip(decoded, pcm)) / len(pcm) < 64
+
+try:
+    idsp.read_idsp(b"RIFF")
+    assert False
+except ValueError:
+    pass
+"#
+                ),
+                None,
+                Some(&locals),
+            )
+            .unwrap();
+        });
+    }
+}
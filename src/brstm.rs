@@ -0,0 +1,444 @@
+//! Nintendo's `.brstm` streams, as used by Wii games.
+//!
+//! A BRSTM file is big endian: an `RSTM` header pointing at three chunks, each padded to
+//! 0x20 bytes.
+//!
+//! * `HEAD`: three references, relative to the start of the chunk body, to the stream info
+//!   (codec, loop start, sample count and the block layout), the tracks and the channels. Each
+//!   channel holds its coefficients, gain and start and loop contexts.
+//! * `ADPC`: the two history samples of every channel at the start of each block, which lets
+//!   players seek without decoding from the start.
+//! * `DATA`: the audio, interleaved in blocks. The last block of each channel is padded to
+//!   0x20 bytes.
+//!
+//! A looping BRSTM always loops up to the end of the stream. Only GC-ADPCM streams are
+//! supported.
+
+use crate::{
+    coefficients::Coefficients,
+    container::{AudioContainer, GcAdpcmAudio},
+    idsp::{
+        deinterleave, interleave, Channel, ChannelMetadata, DecodeError, EncodeError,
+        GcAdpcmContext,
+    },
+    math::{get_next_multiple, sample_count_to_byte_count, DivideByRoundUp},
+    BYTES_PER_FRAME, SAMPLES_PER_FRAME,
+};
+use alloc::{vec, vec::Vec};
+use bytes::{Buf, BufMut, BytesMut};
+#[cfg(feature = "std")]
+use std::{fs::File, io::Read, path::Path};
+
+pub(crate) const RSTM_HEADER: &[u8] = b"RSTM";
+const HEAD: &[u8] = b"HEAD";
+const ADPC: &[u8] = b"ADPC";
+const DATA: &[u8] = b"DATA";
+const BYTE_ORDER_MARK: u16 = 0xFEFF;
+const VERSION: u16 = 0x0100;
+const HEADER_SIZE: usize = 0x40;
+const CHUNK_ALIGNMENT: usize = 0x20;
+const CHUNK_HEADER_SIZE: usize = 8;
+const REFERENCE: u32 = 0x0100_0000;
+const CODEC_GC_ADPCM: u8 = 2;
+const STREAM_INFO_SIZE: usize = 0x34;
+const ADPCM_INFO_SIZE: usize = 0x30;
+/// Bytes of history per channel and block in the `ADPC` chunk.
+const HISTORY_SIZE: usize = 4;
+
+/// The number of bytes of each channel in a full block, as written by Nintendo's tools.
+pub const DEFAULT_BLOCK_SIZE: usize = 0x2000;
+
+#[cfg(feature = "std")]
+pub fn read_brstm<P: AsRef<Path>>(file_path: P) -> Result<GcAdpcmAudio, DecodeError> {
+    let mut file = File::open(file_path)?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+
+    read_brstm_bytes(&bytes)
+}
+
+pub fn read_brstm_bytes(original_bytes: &[u8]) -> Result<GcAdpcmAudio, DecodeError> {
+    let mut bytes = original_bytes.get(..HEADER_SIZE).ok_or(DecodeError::InvalidHeader)?;
+    if !bytes.starts_with(RSTM_HEADER) {
+        return Err(DecodeError::InvalidHeader);
+    }
+
+    bytes.advance(RSTM_HEADER.len());
+    if bytes.get_u16() != BYTE_ORDER_MARK {
+        return Err(DecodeError::InvalidHeader);
+    }
+
+    bytes.advance(10);
+    let head_offset = bytes.get_u32() as usize;
+
+    // Offsets in HEAD are relative to the start of its body.
+    let head = original_bytes.get(head_offset..).ok_or(DecodeError::InvalidHeader)?;
+    if !head.starts_with(HEAD) || head.len() < CHUNK_HEADER_SIZE + 3 * 8 {
+        return Err(DecodeError::InvalidHeader);
+    }
+    let head = &head[CHUNK_HEADER_SIZE..];
+    let reference = |index: usize| -> Result<&[u8], DecodeError> {
+        let offset = (&head[index * 8 + 4..]).get_u32() as usize;
+        head.get(offset..).ok_or(DecodeError::InvalidHeader)
+    };
+
+    let mut stream_info = reference(0)?;
+    if stream_info.len() < STREAM_INFO_SIZE {
+        return Err(DecodeError::InvalidHeader);
+    }
+
+    if stream_info.get_u8() != CODEC_GC_ADPCM {
+        return Err(DecodeError::UnsupportedFormat);
+    }
+
+    let looping = stream_info.get_u8() == 1;
+    let channel_count = stream_info.get_u8() as usize;
+    stream_info.advance(1);
+    let sample_rate = stream_info.get_u16() as usize;
+    stream_info.advance(2);
+    let loop_start = stream_info.get_u32() as usize;
+    let sample_count = stream_info.get_u32() as usize;
+    let audio_data_offset = stream_info.get_u32() as usize;
+    let block_count = stream_info.get_u32() as usize;
+    let block_size = stream_info.get_u32() as usize;
+    stream_info.advance(12);
+    let last_block_padded_size = stream_info.get_u32() as usize;
+
+    if channel_count == 0 || block_count == 0 || block_size == 0 {
+        return Err(DecodeError::InvalidHeader);
+    }
+
+    if looping && loop_start >= sample_count {
+        return Err(DecodeError::InvalidHeader);
+    }
+
+    let mut channel_info = reference(2)?;
+    if channel_info.len() < 4 + channel_count * 8 || channel_info.get_u8() as usize != channel_count
+    {
+        return Err(DecodeError::InvalidHeader);
+    }
+    channel_info.advance(3);
+
+    let loop_points = if looping { Some((loop_start, sample_count)) } else { None };
+    let byte_count = sample_count_to_byte_count(sample_count);
+    let mut metadatas = vec![];
+
+    for _ in 0..channel_count {
+        channel_info.advance(4);
+        let mut channel =
+            head.get(channel_info.get_u32() as usize + 4..).ok_or(DecodeError::InvalidHeader)?;
+        if channel.len() < 4 {
+            return Err(DecodeError::InvalidHeader);
+        }
+
+        let mut adpcm_info =
+            head.get(channel.get_u32() as usize..).ok_or(DecodeError::InvalidHeader)?;
+        if adpcm_info.len() < ADPCM_INFO_SIZE {
+            return Err(DecodeError::InvalidHeader);
+        }
+
+        let coefficients =
+            Coefficients::from_be_bytes(adpcm_info).ok_or(DecodeError::InvalidHeader)?;
+        adpcm_info.advance(32);
+
+        let mut metadata =
+            ChannelMetadata::new(sample_count, sample_rate, coefficients, loop_points);
+        metadata.gain = adpcm_info.get_i16();
+        metadata.start_context = GcAdpcmContext::read_from_buf(&mut adpcm_info);
+        metadata.loop_context = GcAdpcmContext::read_from_buf(&mut adpcm_info);
+        metadatas.push(metadata);
+    }
+
+    let channel_len = (block_count - 1)
+        .checked_mul(block_size)
+        .and_then(|len| len.checked_add(last_block_padded_size))
+        .ok_or(DecodeError::InvalidHeader)?;
+    let data_len = channel_len.checked_mul(channel_count).ok_or(DecodeError::InvalidHeader)?;
+    let data = original_bytes.get(audio_data_offset..).ok_or(DecodeError::InvalidAudioLength)?;
+    if data_len > data.len() || byte_count > channel_len {
+        return Err(DecodeError::InvalidAudioLength);
+    }
+    let audio = deinterleave(data, data_len, block_size, channel_count, Some(byte_count))?;
+
+    let channels = metadatas
+        .into_iter()
+        .zip(audio)
+        .map(|(metadata, audio)| Channel { metadata, audio })
+        .collect();
+
+    Ok(GcAdpcmAudio { sample_rate, sample_count, loop_points, channels })
+}
+
+pub fn write_brstm_bytes<C: AudioContainer + ?Sized>(audio: &C) -> Result<Vec<u8>, EncodeError> {
+    write_brstm_bytes_with_block_size(audio, DEFAULT_BLOCK_SIZE)
+}
+
+/// Writes `audio` out with `block_size` bytes of each channel per block. BRSTM streams can
+/// only loop up to their end, so a loop ending earlier is an error.
+pub fn write_brstm_bytes_with_block_size<C: AudioContainer + ?Sized>(
+    audio: &C,
+    block_size: usize,
+) -> Result<Vec<u8>, EncodeError> {
+    let channels = audio.channels();
+    let channel_count = channels.len();
+    let sample_count = audio.sample_count();
+
+    if channel_count == 0 || channel_count > u8::MAX as usize {
+        return Err(EncodeError::InvalidChannelCount);
+    }
+
+    if block_size == 0 || !block_size.is_multiple_of(CHUNK_ALIGNMENT) {
+        return Err(EncodeError::InvalidBlockSize);
+    }
+
+    if audio.sample_rate() > u16::MAX as usize {
+        return Err(EncodeError::InvalidSampleRate);
+    }
+
+    if let Some((loop_start, loop_end)) = audio.loop_points() {
+        if loop_start >= loop_end || loop_end != sample_count {
+            return Err(EncodeError::InvalidLoopPoints);
+        }
+    }
+
+    let byte_count = sample_count_to_byte_count(sample_count);
+    if sample_count == 0 || channels.iter().any(|channel| channel.audio.len() < byte_count) {
+        return Err(EncodeError::InvalidAudioLength);
+    }
+
+    let samples_per_block = block_size / BYTES_PER_FRAME * SAMPLES_PER_FRAME;
+    let block_count = byte_count.divide_by_round_up(block_size);
+    let last_block_size = byte_count - (block_count - 1) * block_size;
+    let last_block_padded_size = get_next_multiple(last_block_size, CHUNK_ALIGNMENT);
+    let last_block_samples = sample_count - (block_count - 1) * samples_per_block;
+    let channel_data_len = (block_count - 1) * block_size + last_block_padded_size;
+
+    let trimmed: Vec<Channel> = channels
+        .iter()
+        .map(|channel| Channel {
+            metadata: channel.metadata.clone(),
+            audio: channel.audio[..byte_count].to_vec(),
+        })
+        .collect();
+    let data = interleave(&trimmed, block_size, Some(channel_data_len));
+
+    // HEAD, laid out relative to the start of its body.
+    let track_count = channel_count.divide_by_round_up(2);
+    let stream_info_offset = 3 * 8;
+    let track_info_offset = stream_info_offset + STREAM_INFO_SIZE;
+    let channel_info_offset = track_info_offset + 4 + track_count * (8 + 4);
+    let channel_entries_offset = channel_info_offset + 4 + channel_count * 8;
+
+    let mut head = BytesMut::new();
+    for &offset in &[stream_info_offset, track_info_offset, channel_info_offset] {
+        head.put_u32(REFERENCE);
+        head.put_u32(offset as u32);
+    }
+
+    let head_len = get_next_multiple(
+        CHUNK_HEADER_SIZE + channel_entries_offset + channel_count * (8 + ADPCM_INFO_SIZE),
+        CHUNK_ALIGNMENT,
+    );
+    let adpc_len = get_next_multiple(
+        CHUNK_HEADER_SIZE + block_count * channel_count * HISTORY_SIZE,
+        CHUNK_ALIGNMENT,
+    );
+    let audio_data_offset = HEADER_SIZE + head_len + adpc_len + CHUNK_ALIGNMENT;
+    let loop_start = audio.loop_points().map_or(0, |(start, _)| start);
+
+    head.put_u8(CODEC_GC_ADPCM);
+    head.put_u8(audio.loop_points().is_some() as u8);
+    head.put_u8(channel_count as u8);
+    head.put_u8(0);
+    head.put_u16(audio.sample_rate() as u16);
+    head.put_u16(0);
+    head.put_u32(loop_start as u32);
+    head.put_u32(sample_count as u32);
+    head.put_u32(audio_data_offset as u32);
+    head.put_u32(block_count as u32);
+    head.put_u32(block_size as u32);
+    head.put_u32(samples_per_block as u32);
+    head.put_u32(last_block_size as u32);
+    head.put_u32(last_block_samples as u32);
+    head.put_u32(last_block_padded_size as u32);
+    head.put_u32(samples_per_block as u32);
+    head.put_u32(HISTORY_SIZE as u32);
+
+    // One track per pair of channels.
+    head.put_u8(track_count as u8);
+    head.put_u8(0);
+    head.put_u16(0);
+    let tracks_offset = track_info_offset + 4 + track_count * 8;
+    for track in 0..track_count {
+        head.put_u32(REFERENCE);
+        head.put_u32((tracks_offset + track * 4) as u32);
+    }
+    for track in 0..track_count {
+        let first = track * 2;
+        let count = (channel_count - first).min(2);
+        head.put_u8(count as u8);
+        head.put_u8(first as u8);
+        head.put_u8(if count == 2 { first as u8 + 1 } else { 0 });
+        head.put_u8(0);
+    }
+
+    head.put_u8(channel_count as u8);
+    head.put_slice(&[0; 3]);
+    for i in 0..channel_count {
+        head.put_u32(REFERENCE);
+        head.put_u32((channel_entries_offset + i * (8 + ADPCM_INFO_SIZE)) as u32);
+    }
+    for (i, channel) in channels.iter().enumerate() {
+        let metadata = &channel.metadata;
+        head.put_u32(REFERENCE);
+        head.put_u32((channel_entries_offset + i * (8 + ADPCM_INFO_SIZE) + 8) as u32);
+
+        let adpcm_info_start = head.len();
+        head.extend_from_slice(&metadata.coefficients.to_be_bytes());
+        head.put_i16(metadata.gain);
+        metadata.start_context.write_to_buf(&mut head);
+        metadata.loop_context.write_to_buf(&mut head);
+        head.resize(adpcm_info_start + ADPCM_INFO_SIZE, 0);
+    }
+
+    // The history at the start of each block. Blocks hold whole frames, so this only
+    // needs the samples before each block boundary.
+    let mut adpc = BytesMut::new();
+    let pcm: Vec<Vec<i16>> = channels.iter().map(Channel::decode).collect();
+    for block in 0..block_count {
+        let sample = block * samples_per_block;
+        for channel in &pcm {
+            let history = |offset: usize| sample.checked_sub(offset).map_or(0, |i| channel[i]);
+            adpc.put_i16(history(1));
+            adpc.put_i16(history(2));
+        }
+    }
+
+    let mut bytes = BytesMut::new();
+    let data_chunk_len = CHUNK_ALIGNMENT + data.len();
+    let file_len = HEADER_SIZE + head_len + adpc_len + data_chunk_len;
+
+    bytes.extend_from_slice(RSTM_HEADER);
+    bytes.put_u16(BYTE_ORDER_MARK);
+    bytes.put_u16(VERSION);
+    bytes.put_u32(file_len as u32);
+    bytes.put_u16(HEADER_SIZE as u16);
+    bytes.put_u16(3);
+    for &(offset, len) in &[
+        (HEADER_SIZE, head_len),
+        (HEADER_SIZE + head_len, adpc_len),
+        (HEADER_SIZE + head_len + adpc_len, data_chunk_len),
+    ] {
+        bytes.put_u32(offset as u32);
+        bytes.put_u32(len as u32);
+    }
+    bytes.resize(HEADER_SIZE, 0);
+
+    for &(magic, len, body) in &[(HEAD, head_len, &head[..]), (ADPC, adpc_len, &adpc[..])] {
+        let chunk_start = bytes.len();
+        bytes.extend_from_slice(magic);
+        bytes.put_u32(len as u32);
+        bytes.extend_from_slice(body);
+        bytes.resize(chunk_start + len, 0);
+    }
+
+    bytes.extend_from_slice(DATA);
+    bytes.put_u32(data_chunk_len as u32);
+    bytes.put_u32((CHUNK_ALIGNMENT - CHUNK_HEADER_SIZE) as u32);
+    bytes.resize(audio_data_offset, 0);
+    bytes.extend_from_slice(&data);
+
+    Ok(bytes.to_vec())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        dsp::{read_dsp_channels, write_dsp_channels},
+        idsp::{read_idsp_bytes, write_idsp_bytes, IdspContainer},
+        IdspBuilder,
+    };
+
+    fn stereo_loop() -> IdspContainer {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let pcm = read_idsp_bytes(idsp_bytes).unwrap().channels[0].decode();
+        let reversed = pcm.iter().rev().copied().collect();
+
+        IdspBuilder::new(48000)
+            .channel(pcm.clone())
+            .channel(reversed)
+            .loop_points(1400, pcm.len())
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_brstm_roundtrip() {
+        let container = stereo_loop();
+        let bytes = write_brstm_bytes(&container).unwrap();
+        assert_eq!(&bytes[8..12], &(bytes.len() as u32).to_be_bytes());
+
+        let audio = read_brstm_bytes(&bytes).unwrap();
+        assert_eq!(audio.loop_points(), container.loop_points());
+        assert_eq!(audio.sample_count, container.sample_count);
+        assert_eq!(audio.channels, container.channels);
+        assert_eq!(write_brstm_bytes(&audio).unwrap(), bytes);
+
+        // Blocks that don't divide the audio evenly.
+        let small_blocks = write_brstm_bytes_with_block_size(&container, 0x1A0).unwrap();
+        assert_eq!(read_brstm_bytes(&small_blocks).unwrap().channels, container.channels);
+
+        let mut early_loop_end = container.clone();
+        early_loop_end.loop_end -= 14;
+        assert!(matches!(write_brstm_bytes(&early_loop_end), Err(EncodeError::InvalidLoopPoints)));
+
+        let fast = IdspContainer { sample_rate: 96000, ..container };
+        assert!(matches!(write_brstm_bytes(&fast), Err(EncodeError::InvalidSampleRate)));
+    }
+
+    #[test]
+    fn test_corrupt_blocks() {
+        let bytes = write_brstm_bytes(&stereo_loop()).unwrap();
+        let read_u32 = |offset: usize| {
+            u32::from_be_bytes([
+                bytes[offset],
+                bytes[offset + 1],
+                bytes[offset + 2],
+                bytes[offset + 3],
+            ]) as usize
+        };
+        let head_body = read_u32(0x10) + CHUNK_HEADER_SIZE;
+        let stream_info = head_body + read_u32(head_body + 4);
+        assert_eq!(read_u32(stream_info + 0x0C), 47035);
+
+        // Block count, block size and the padded size of the last block.
+        for &field in &[0x14, 0x18, 0x28] {
+            for &value in &[0x7FFF_FFFFu32, 0xFFFF_FFFF] {
+                let mut corrupt = bytes.clone();
+                let offset = stream_info + field;
+                corrupt[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+                assert!(read_brstm_bytes(&corrupt).is_err(), "{:#x} at {:#x}", value, field);
+            }
+        }
+    }
+
+    #[test]
+    fn test_lossless_conversion() {
+        let container = stereo_loop();
+        let pcm = container.decode_channels();
+
+        let brstm = read_brstm_bytes(&write_brstm_bytes(&container).unwrap()).unwrap();
+        let from_brstm: IdspContainer = brstm.convert();
+        assert_eq!(from_brstm.decode_channels(), pcm);
+
+        let dsps = write_dsp_channels(&from_brstm);
+        let dsps: Vec<&[u8]> = dsps.iter().map(Vec::as_slice).collect();
+        let from_dsp = IdspContainer::from(read_dsp_channels(&dsps).unwrap());
+        assert_eq!(from_dsp.decode_channels(), pcm);
+
+        let idsp = read_idsp_bytes(&write_idsp_bytes(&from_dsp).unwrap()).unwrap();
+        assert_eq!(idsp.decode_channels(), pcm);
+        assert_eq!(idsp.channels, container.channels);
+    }
+}
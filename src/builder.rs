@@ -0,0 +1,321 @@
+use crate::{
+    coefficients::Coefficients,
+    encode::encode_gc_adpcm,
+    idsp::{
+        BlockPadding, Channel, EncodeError, IdspContainer, IdspDialect, CHANNEL_INFO_SIZE,
+        STREAM_INFO_SIZE,
+    },
+    resample::{resample, scale_position, ResampleQuality},
+    BYTES_PER_FRAME, SAMPLES_PER_FRAME,
+};
+use alloc::{vec, vec::Vec};
+
+pub use crate::idsp::DEFAULT_INTERLEAVE_SIZE;
+
+/// How [`IdspBuilder`] moves a loop start that falls inside a frame onto a frame boundary.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LoopAlignment {
+    /// Keep the loop where it is.
+    #[default]
+    None,
+    /// Move the loop start back to the previous frame boundary and the loop end back by the
+    /// same amount, filling the gap before the old start with the end of the loop. The loop
+    /// plays back the same, only the last few samples before it are replaced.
+    PreRoll,
+    /// Insert silence at the start of the audio, pushing the loop forward to the next
+    /// frame boundary.
+    Pad,
+}
+
+/// Encodes PCM channels into an [`IdspContainer`].
+#[derive(Clone, Debug)]
+pub struct IdspBuilder {
+    sample_rate: usize,
+    channels: Vec<Vec<i16>>,
+    loop_points: Option<(usize, usize)>,
+    loop_alignment: LoopAlignment,
+    interleave_size: usize,
+    resample: Option<(usize, ResampleQuality)>,
+}
+
+impl IdspBuilder {
+    pub fn new(sample_rate: usize) -> Self {
+        Self {
+            sample_rate,
+            channels: vec![],
+            loop_points: None,
+            loop_alignment: LoopAlignment::None,
+            interleave_size: DEFAULT_INTERLEAVE_SIZE,
+            resample: None,
+        }
+    }
+
+    /// Adds a channel. Every channel must have the same number of samples.
+    pub fn channel(mut self, pcm: Vec<i16>) -> Self {
+        self.channels.push(pcm);
+        self
+    }
+
+    /// Loops the audio from `start` up to `end`, exclusive.
+    pub fn loop_points(mut self, start: usize, end: usize) -> Self {
+        self.loop_points = Some((start, end));
+        self
+    }
+
+    pub fn loop_alignment(mut self, loop_alignment: LoopAlignment) -> Self {
+        self.loop_alignment = loop_alignment;
+        self
+    }
+
+    /// Bytes of each channel per block, which [`build`](Self::build) checks the same way as
+    /// [`IdspContainer::reblock`].
+    pub fn interleave_size(mut self, interleave_size: usize) -> Self {
+        self.interleave_size = interleave_size;
+        self
+    }
+
+    /// Converts the channels to `sample_rate` before encoding, scaling the loop points to
+    /// match.
+    pub fn resample(mut self, sample_rate: usize, quality: ResampleQuality) -> Self {
+        self.resample = Some((sample_rate, quality));
+        self
+    }
+
+    pub fn build(mut self) -> Result<IdspContainer, EncodeError> {
+        let sample_count = match self.channels.first() {
+            Some(channel) => channel.len(),
+            None => return Err(EncodeError::InvalidChannelCount),
+        };
+
+        if sample_count == 0 || self.channels.iter().any(|channel| channel.len() != sample_count) {
+            return Err(EncodeError::InvalidAudioLength);
+        }
+
+        if let Some((start, end)) = self.loop_points {
+            if start >= end || end > sample_count {
+                return Err(EncodeError::InvalidLoopPoints);
+            }
+        }
+
+        if self.sample_rate == 0 || matches!(self.resample, Some((0, _))) {
+            return Err(EncodeError::InvalidSampleRate);
+        }
+
+        if !self.interleave_size.is_multiple_of(BYTES_PER_FRAME) {
+            return Err(EncodeError::InvalidBlockSize);
+        }
+
+        if let Some((sample_rate, quality)) = self.resample {
+            let from_rate = self.sample_rate;
+            for pcm in &mut self.channels {
+                *pcm = resample(pcm, from_rate, sample_rate, quality);
+            }
+
+            let sample_count = self.channels[0].len();
+            if sample_count == 0 {
+                return Err(EncodeError::InvalidAudioLength);
+            }
+
+            if let Some((start, end)) = self.loop_points {
+                let start = scale_position(start, from_rate, sample_rate);
+                let end = scale_position(end, from_rate, sample_rate).min(sample_count);
+
+                if start >= end {
+                    return Err(EncodeError::InvalidLoopPoints);
+                }
+
+                self.loop_points = Some((start, end));
+            }
+
+            self.sample_rate = sample_rate;
+        }
+
+        let mut loop_points = self.loop_points;
+        let mut channels = vec![];
+
+        for pcm in self.channels {
+            let (pcm, aligned_loop_points) = match self.loop_points {
+                Some((start, end)) => {
+                    let (pcm, start, end) = align_loop(pcm, start, end, self.loop_alignment);
+                    (pcm, Some((start, end)))
+                },
+                None => (pcm, None),
+            };
+
+            let coefficients = Coefficients::from(&pcm);
+            let audio = encode_gc_adpcm(&pcm, &coefficients);
+
+            loop_points = aligned_loop_points;
+            channels.push(Channel::from_adpcm(
+                audio.data,
+                coefficients,
+                audio.sample_count,
+                self.sample_rate,
+                loop_points,
+            ));
+        }
+
+        let (loop_start, loop_end) = loop_points.unwrap_or((0, 0));
+
+        Ok(IdspContainer {
+            looping: loop_points.is_some(),
+            channel_count: channels.len(),
+            sample_rate: self.sample_rate,
+            loop_start,
+            loop_end,
+            sample_count: channels[0].metadata.sample_count,
+            interleave_size: self.interleave_size,
+            padding: BlockPadding::FullBlock,
+            header_size: STREAM_INFO_SIZE,
+            channel_info_size: CHANNEL_INFO_SIZE,
+            dialect: IdspDialect::BigEndian,
+            channels,
+        })
+    }
+}
+
+/// Moves the loop `start..end` of `pcm` onto a frame boundary, returning the new audio and
+/// loop points.
+pub fn align_loop(
+    mut pcm: Vec<i16>,
+    start: usize,
+    end: usize,
+    loop_alignment: LoopAlignment,
+) -> (Vec<i16>, usize, usize) {
+    let offset = start % SAMPLES_PER_FRAME;
+
+    if offset == 0 {
+        return (pcm, start, end);
+    }
+
+    match loop_alignment {
+        LoopAlignment::None => (pcm, start, end),
+        LoopAlignment::PreRoll => {
+            // Rotating the loop right by `offset` puts its last samples right before the old
+            // start, which is where they would play when the loop wraps around anyway.
+            let mut looped = pcm[start..end].to_vec();
+            looped.rotate_right(offset % (end - start));
+            pcm.splice(start - offset..end, looped);
+
+            (pcm, start - offset, end - offset)
+        },
+        LoopAlignment::Pad => {
+            let padding = SAMPLES_PER_FRAME - offset;
+            pcm.splice(0..0, core::iter::repeat_n(0, padding));
+
+            (pcm, start + padding, end + padding)
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::idsp::{read_idsp_bytes, write_idsp_bytes};
+
+    #[test]
+    fn test_build_aligned_loop() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+        let pcm = idsp_file.channels[0].decode();
+
+        let container = IdspBuilder::new(idsp_file.sample_rate)
+            .channel(pcm.clone())
+            .channel(pcm.clone())
+            .loop_points(1000, 40000)
+            .loop_alignment(LoopAlignment::PreRoll)
+            .build()
+            .unwrap();
+
+        assert_eq!(container.channel_count, 2);
+        assert_eq!((container.loop_start, container.loop_end), (994, 39994));
+        assert_eq!(container.sample_count, pcm.len() - 6);
+        assert_eq!(container.validate(), vec![]);
+        write_idsp_bytes(&container).unwrap();
+
+        let padded = IdspBuilder::new(idsp_file.sample_rate)
+            .channel(pcm.clone())
+            .loop_points(1000, 40000)
+            .loop_alignment(LoopAlignment::Pad)
+            .build()
+            .unwrap();
+
+        assert_eq!((padded.loop_start, padded.loop_end), (1008, 40008));
+        assert_eq!(padded.sample_count, pcm.len() + 8);
+        assert_eq!(padded.validate(), vec![]);
+
+        let blocked = |interleave_size| {
+            IdspBuilder::new(idsp_file.sample_rate)
+                .channel(pcm.clone())
+                .interleave_size(interleave_size)
+                .build()
+        };
+        assert_eq!(blocked(0).unwrap().interleave_size, 0);
+        assert_eq!(blocked(0x100).unwrap().interleave_size, 0x100);
+        assert!(matches!(blocked(0x101), Err(EncodeError::InvalidBlockSize)));
+    }
+
+    #[test]
+    fn test_build_resampled() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+        let pcm = idsp_file.channels[0].decode();
+        assert_eq!(idsp_file.sample_rate, 48000);
+
+        let container = IdspBuilder::new(48000)
+            .channel(pcm.clone())
+            .loop_points(3000, 30000)
+            .loop_alignment(LoopAlignment::PreRoll)
+            .resample(32000, ResampleQuality::Linear)
+            .build()
+            .unwrap();
+
+        assert_eq!(container.sample_rate, 32000);
+        assert!(container.channels.iter().all(|channel| channel.metadata.sample_rate == 32000));
+        assert_eq!((container.loop_start, container.loop_end), (1988, 19988));
+        assert_eq!(container.sample_count, scale_position(pcm.len(), 48000, 32000) - 12);
+        assert_eq!(container.validate(), vec![]);
+
+        let build = |from_rate, to_rate| {
+            IdspBuilder::new(from_rate)
+                .channel(pcm.clone())
+                .resample(to_rate, ResampleQuality::Linear)
+                .build()
+        };
+        assert!(matches!(build(0, 32000), Err(EncodeError::InvalidSampleRate)));
+        assert!(matches!(build(48000, 0), Err(EncodeError::InvalidSampleRate)));
+        assert!(matches!(
+            IdspBuilder::new(0).channel(pcm).build(),
+            Err(EncodeError::InvalidSampleRate)
+        ));
+    }
+
+    #[test]
+    fn test_align_loop_playback() {
+        let pcm: Vec<i16> = (0..100).collect();
+        // Plays the intro once and then the loop forever, up to `len` samples.
+        let play = |pcm: &[i16], start: usize, end: usize, len: usize| -> Vec<i16> {
+            let mut out = pcm[..end].to_vec();
+            while out.len() < len {
+                out.extend_from_slice(&pcm[start..end]);
+            }
+            out.truncate(len);
+            out
+        };
+
+        let expected = play(&pcm, 20, 60, 500);
+
+        let (pre_roll, start, end) = align_loop(pcm.clone(), 20, 60, LoopAlignment::PreRoll);
+        assert_eq!((start, end, pre_roll.len()), (14, 54, 94));
+        assert_eq!(play(&pre_roll, start, end, 500)[20..], expected[20..]);
+
+        // Loops shorter than the pre-roll still play back the same.
+        let (pre_roll, start, end) = align_loop(pcm.clone(), 20, 24, LoopAlignment::PreRoll);
+        assert_eq!((start, end), (14, 18));
+        assert_eq!(play(&pre_roll, start, end, 500)[20..], play(&pcm, 20, 24, 500)[20..]);
+
+        let (padded, start, end) = align_loop(pcm.clone(), 20, 60, LoopAlignment::Pad);
+        assert_eq!((start, end), (28, 68));
+        assert_eq!(play(&padded, start, end, 508)[8..], expected[..]);
+    }
+}
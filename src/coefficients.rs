@@ -1,11 +1,19 @@
-use crate::{math::DivideByRoundUp, SAMPLES_PER_FRAME};
+use crate::{
+    math::{round, DivideByRoundUp},
+    SAMPLES_PER_FRAME,
+};
+use alloc::{vec, vec::Vec};
 
-#[derive(Debug)]
+const COEFFICIENTS_BYTE_LEN: usize = 32;
+
+/// The eight (coef_1, coef_2) predictor pairs of a GC-ADPCM stream, in 5.11 fixed point.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
 pub struct Coefficients {
     pub coefs: [i16; 16],
 }
 
-impl std::ops::Deref for Coefficients {
+impl core::ops::Deref for Coefficients {
     type Target = [i16; 16];
 
     fn deref(&self) -> &Self::Target {
@@ -15,9 +23,101 @@ impl std::ops::Deref for Coefficients {
 
 impl<T: AsRef<[i16]>> From<T> for Coefficients {
     fn from(source: T) -> Self {
-        let source = source.as_ref();
-        let frame_count = source.len().divide_by_round_up(SAMPLES_PER_FRAME);
-        let mut pcm_hist = [0i16; SAMPLES_PER_FRAME * 2];
+        Self::from_many(&[source.as_ref()])
+    }
+}
+
+impl Coefficients {
+    pub fn new(coefs: [i16; 16]) -> Self {
+        Self { coefs }
+    }
+
+    /// Reads 16 big-endian `i16`s, as stored in DSP-style headers.
+    pub fn from_be_bytes(bytes: &[u8]) -> Option<Self> {
+        Self::from_bytes(bytes, i16::from_be_bytes)
+    }
+
+    pub fn from_le_bytes(bytes: &[u8]) -> Option<Self> {
+        Self::from_bytes(bytes, i16::from_le_bytes)
+    }
+
+    fn from_bytes(bytes: &[u8], read: fn([u8; 2]) -> i16) -> Option<Self> {
+        let bytes = bytes.get(..COEFFICIENTS_BYTE_LEN)?;
+        let mut coefs = [0i16; 16];
+
+        for (coef, pair) in coefs.iter_mut().zip(bytes.chunks_exact(2)) {
+            *coef = read([pair[0], pair[1]]);
+        }
+
+        Some(Self { coefs })
+    }
+
+    pub fn to_be_bytes(self) -> [u8; COEFFICIENTS_BYTE_LEN] {
+        self.to_bytes(i16::to_be_bytes)
+    }
+
+    pub fn to_le_bytes(self) -> [u8; COEFFICIENTS_BYTE_LEN] {
+        self.to_bytes(i16::to_le_bytes)
+    }
+
+    fn to_bytes(self, write: fn(i16) -> [u8; 2]) -> [u8; COEFFICIENTS_BYTE_LEN] {
+        let mut bytes = [0u8; COEFFICIENTS_BYTE_LEN];
+
+        for (pair, coef) in bytes.chunks_exact_mut(2).zip(self.coefs.iter()) {
+            pair.copy_from_slice(&write(*coef));
+        }
+
+        bytes
+    }
+
+    /// Returns the (coef_1, coef_2) pair selected by a frame header's predictor nibble.
+    ///
+    /// Panics if `index` is not in `0..8`.
+    pub fn predictor(&self, index: usize) -> (i16, i16) {
+        (self.coefs[index * 2], self.coefs[index * 2 + 1])
+    }
+
+    /// Whether the poles of predictor `index` lie inside the unit circle. An unstable
+    /// predictor makes decoding errors grow instead of decaying.
+    pub fn is_predictor_stable(&self, index: usize) -> bool {
+        let (coef_1, coef_2) = self.predictor(index);
+        let (coef_1, coef_2) = (coef_1 as i32, coef_2 as i32);
+
+        coef_2.abs() < 2048 && coef_1.abs() < 2048 - coef_2
+    }
+
+    /// Whether predictor `index` has a pole on the unit circle, like the hold-last-sample
+    /// predictor `(2048, 0)`, so decoding errors neither grow nor decay.
+    pub fn is_predictor_marginal(&self, index: usize) -> bool {
+        let (coef_1, coef_2) = self.predictor(index);
+        let (coef_1, coef_2) = (coef_1 as i32, coef_2 as i32);
+
+        !self.is_predictor_stable(index) && coef_2.abs() <= 2048 && coef_1.abs() <= 2048 - coef_2
+    }
+
+    pub fn unstable_predictors(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..8).filter(move |&i| !self.is_predictor_stable(i))
+    }
+
+    pub fn is_stable(&self) -> bool {
+        self.unstable_predictors().next().is_none()
+    }
+
+    /// Generates one coefficient set from several PCM inputs, such as the channels of a
+    /// stereo asset or every sound in a bank. Each input is analyzed from silence, and the
+    /// records from all of them are merged as if they came from a single input.
+    pub fn from_many(sources: &[&[i16]]) -> Self {
+        Self::from_many_with_diagnostics(sources).0
+    }
+
+    /// Like [`Coefficients::from_many`], but also reports how many frames contributed to the
+    /// result and why the others were skipped.
+    ///
+    /// If no frame yields a usable record (empty, silent or constant input, for example) the
+    /// result is all zeros, which encodes every frame as scaled deltas from zero.
+    pub fn from_many_with_diagnostics(sources: &[&[i16]]) -> (Self, CoefficientDiagnostics) {
+        let frame_count: usize =
+            sources.iter().map(|source| source.len().divide_by_round_up(SAMPLES_PER_FRAME)).sum();
         let mut coefs = [0i16; 16];
         let mut vec1 = [0f64; 3];
         let mut vec2 = [0f64; 3];
@@ -27,24 +127,41 @@ impl<T: AsRef<[i16]>> From<T> for Coefficients {
         let mut records = vec![vec![0f64; 3]; frame_count * 2];
         let mut record_count = 0;
         let mut vec_best = [[0f64; 3]; 8];
-
-        for frame in source.chunks(SAMPLES_PER_FRAME) {
-            pcm_hist[SAMPLES_PER_FRAME..SAMPLES_PER_FRAME + frame.len()].copy_from_slice(frame);
-
-            inner_product_merge(&mut vec1, &pcm_hist);
-            if vec1[0].abs() > 10.0 {
-                outer_product_merge(&mut mtx, &pcm_hist);
-                if !analyze_ranges(&mut mtx, &mut vec_idxs, &mut buffer) {
-                    bidirectional_filter(&mut mtx, &mut vec_idxs, &mut vec1);
-                    if !quadratic_merge(&mut vec1) {
-                        finish_record(&mut vec1, &mut records[record_count]);
-                        record_count += 1;
+        let mut diagnostics = CoefficientDiagnostics { frame_count, ..Default::default() };
+
+        for source in sources {
+            let mut pcm_hist = [0i16; SAMPLES_PER_FRAME * 2];
+
+            for frame in source.chunks(SAMPLES_PER_FRAME) {
+                pcm_hist[SAMPLES_PER_FRAME..SAMPLES_PER_FRAME + frame.len()].copy_from_slice(frame);
+
+                inner_product_merge(&mut vec1, &pcm_hist);
+                if vec1[0].abs() > 10.0 {
+                    outer_product_merge(&mut mtx, &pcm_hist);
+                    if !analyze_ranges(&mut mtx, &mut vec_idxs, &mut buffer) {
+                        bidirectional_filter(&mut mtx, &mut vec_idxs, &mut vec1);
+                        if !quadratic_merge(&mut vec1) {
+                            finish_record(&mut vec1, &mut records[record_count]);
+                            record_count += 1;
+                        } else {
+                            diagnostics.rejected_unstable += 1;
+                        }
+                    } else {
+                        diagnostics.rejected_singular += 1;
                     }
+                } else {
+                    diagnostics.rejected_low_energy += 1;
                 }
+
+                let (a, b) = pcm_hist.split_at_mut(SAMPLES_PER_FRAME);
+                a.copy_from_slice(b);
             }
+        }
 
-            let (a, b) = pcm_hist.split_at_mut(SAMPLES_PER_FRAME);
-            a.copy_from_slice(b);
+        diagnostics.records_accepted = record_count;
+
+        if record_count == 0 {
+            return (Self::default(), diagnostics);
         }
 
         vec1[0] = 1.0;
@@ -58,11 +175,11 @@ impl<T: AsRef<[i16]>> From<T> for Coefficients {
             }
         }
 
-        for y in 1..=2 {
-            vec1[y] /= record_count as f64;
+        for value in &mut vec1[1..] {
+            *value /= record_count as f64;
         }
 
-        merge_finish_record(&mut vec1, &mut vec_best[0]);
+        merge_finish_record(&vec1, &mut vec_best[0]);
 
         let mut exp = 1;
         let mut w = 0;
@@ -83,36 +200,62 @@ impl<T: AsRef<[i16]>> From<T> for Coefficients {
         for z in 0..8 {
             let mut d = -vec_best[z][1] * 2048.0;
             if d > 0.0 {
-                coefs[z * 2] = if d > std::i16::MAX as f64 {
-                    i16::MAX
-                } else {
-                    d.round() as i16
-                };
+                coefs[z * 2] = if d > i16::MAX as f64 { i16::MAX } else { round(d) as i16 };
             } else {
-                coefs[z * 2] = if d < std::i16::MIN as f64 {
-                    i16::MIN
-                } else {
-                    d.round() as i16
-                };
+                coefs[z * 2] = if d < i16::MIN as f64 { i16::MIN } else { round(d) as i16 };
             }
 
             d = -vec_best[z][2] * 2048.0;
             if d > 0.0 {
-                coefs[z * 2 + 1] = if d > std::i16::MAX as f64 {
-                    i16::MAX
-                } else {
-                    d.round() as i16
-                };
+                coefs[z * 2 + 1] = if d > i16::MAX as f64 { i16::MAX } else { round(d) as i16 };
             } else {
-                coefs[z * 2 + 1] = if d < std::i16::MIN as f64 {
-                    i16::MIN
-                } else {
-                    d.round() as i16
-                };
+                coefs[z * 2 + 1] = if d < i16::MIN as f64 { i16::MIN } else { round(d) as i16 };
             };
         }
 
-        Self { coefs }
+        let mut coefficients = Self { coefs };
+
+        // Pull any predictor on or past the edge of stability just inside it, one step towards
+        // zero at a time, so errors always decay.
+        for i in 0..8 {
+            if coefficients.is_predictor_stable(i) {
+                continue;
+            }
+
+            diagnostics.stabilized_predictors.push(i);
+            while !coefficients.is_predictor_stable(i) {
+                for coef in &mut coefficients.coefs[i * 2..i * 2 + 2] {
+                    *coef -= coef.signum();
+                }
+            }
+        }
+
+        (coefficients, diagnostics)
+    }
+}
+
+/// Frame statistics from coefficient generation.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CoefficientDiagnostics {
+    /// Frames seen across all inputs.
+    pub frame_count: usize,
+    /// Frames that produced a record for the predictor search.
+    pub records_accepted: usize,
+    /// Frames too quiet to analyze, such as silence.
+    pub rejected_low_energy: usize,
+    /// Frames whose autocorrelation matrix was singular, such as DC or a clipped plateau.
+    pub rejected_singular: usize,
+    /// Frames whose best-fit predictor was unstable.
+    pub rejected_unstable: usize,
+    /// Predictors that came out marginally stable or unstable, like the hold-last-sample
+    /// predictor constant input yields, and were moved just inside the stable region.
+    pub stabilized_predictors: Vec<usize>,
+}
+
+impl CoefficientDiagnostics {
+    /// Whether no frame was usable and the all-zero fallback was returned.
+    pub fn used_fallback(&self) -> bool {
+        self.records_accepted == 0
     }
 }
 
@@ -156,8 +299,8 @@ fn analyze_ranges(mtx: &mut [[f64; 3]], vec_idxs: &mut [usize], recips: &mut [f6
     for i in 1..=2 {
         for x in 1..i {
             tmp = mtx[x][i];
-            for y in 1..x {
-                tmp -= mtx[x][y] * mtx[y][i];
+            for (y, row) in mtx[..x].iter().enumerate().skip(1) {
+                tmp -= mtx[x][y] * row[i];
             }
             mtx[x][i] = tmp;
         }
@@ -165,8 +308,8 @@ fn analyze_ranges(mtx: &mut [[f64; 3]], vec_idxs: &mut [usize], recips: &mut [f6
         val = 0.0;
         for x in i..=2 {
             tmp = mtx[x][i];
-            for y in 1..i {
-                tmp -= mtx[x][y] * mtx[y][i];
+            for (y, row) in mtx[..i].iter().enumerate().skip(1) {
+                tmp -= mtx[x][y] * row[i];
             }
 
             mtx[x][i] = tmp;
@@ -178,19 +321,17 @@ fn analyze_ranges(mtx: &mut [[f64; 3]], vec_idxs: &mut [usize], recips: &mut [f6
         }
 
         if max_index != i {
-            for y in 1..=2 {
-                tmp = mtx[max_index][y];
-                mtx[max_index][y] = mtx[i][y];
-                mtx[i][y] = tmp;
-            }
+            let (max_row, row) = (mtx[max_index], mtx[i]);
+            mtx[max_index][1..].copy_from_slice(&row[1..]);
+            mtx[i][1..].copy_from_slice(&max_row[1..]);
             recips[max_index] = recips[i];
         }
         vec_idxs[i] = max_index;
 
         if i != 2 {
             tmp = 1.0 / mtx[i][i];
-            for x in (i + 1)..=2 {
-                mtx[x][i] *= tmp;
+            for row in &mut mtx[i + 1..=2] {
+                row[i] *= tmp;
             }
         }
     }
@@ -198,8 +339,8 @@ fn analyze_ranges(mtx: &mut [[f64; 3]], vec_idxs: &mut [usize], recips: &mut [f6
     min = 1.0e10;
     max = 0.0;
 
-    for i in 1..=2 {
-        tmp = mtx[i][i].abs();
+    for (i, row) in mtx[..=2].iter().enumerate().skip(1) {
+        tmp = row[i].abs();
         if tmp < min {
             min = tmp;
         }
@@ -257,11 +398,11 @@ fn quadratic_merge(in_out: &mut [f64]) -> bool {
 }
 
 fn finish_record(in_r: &mut [f64], out_r: &mut [f64]) {
-    for z in 1..=2 {
-        if in_r[z] >= 1.0 {
-            in_r[z] = 0.9999999999;
-        } else if in_r[z] <= -1.0 {
-            in_r[z] = -0.9999999999;
+    for value in &mut in_r[1..=2] {
+        if *value >= 1.0 {
+            *value = 0.9999999999;
+        } else if *value <= -1.0 {
+            *value = -0.9999999999;
         }
     }
 
@@ -343,15 +484,13 @@ fn filter_records(
     for _x in 0..2 {
         for y in 0..exp {
             buffer1[y] = 0;
-            for i in 0..=2 {
-                buffer_list[y][i] = 0.0;
-            }
+            buffer_list[y] = [0.0; 3];
         }
         for z in 0..record_count {
             let mut index = 0;
             let mut value = 1.0e30;
-            for i in 0..exp {
-                let temp_val = contrast_vectors(&mut vec_best[i], &mut records[z]);
+            for (i, best) in vec_best[..exp].iter().enumerate() {
+                let temp_val = contrast_vectors(best, &records[z]);
                 if temp_val < value {
                     value = temp_val;
                     index = i;
@@ -366,14 +505,133 @@ fn filter_records(
 
         for i in 0..exp {
             if buffer1[i] > 0 {
-                for y in 0..=2 {
-                    buffer_list[i][y] /= buffer1[i] as f64;
+                for value in &mut buffer_list[i] {
+                    *value /= buffer1[i] as f64;
                 }
             }
         }
 
         for i in 0..exp {
-            merge_finish_record(&mut buffer_list[i], &mut vec_best[i]);
+            merge_finish_record(&buffer_list[i], &mut vec_best[i]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        coefficients::Coefficients, decode::decode_gc_adpcm, encode::encode_gc_adpcm,
+        idsp::read_idsp_bytes,
+    };
+
+    const COEFS: [i16; 16] = [
+        3089, -1379, 3980, -2016, 3865, -1986, 3653, -1641, 3459, -1581, 4011, -2005, 3653, -1692,
+        3956, -1920,
+    ];
+
+    #[test]
+    fn test_byte_roundtrip() {
+        let coefficients = Coefficients::new(COEFS);
+
+        let be = coefficients.to_be_bytes();
+        assert_eq!(&be[..4], &[0x0c, 0x11, 0xfa, 0x9d]);
+        assert_eq!(Coefficients::from_be_bytes(&be), Some(coefficients));
+        assert_eq!(Coefficients::from_le_bytes(&coefficients.to_le_bytes()), Some(coefficients));
+        assert_eq!(Coefficients::from_be_bytes(&be[..31]), None);
+    }
+
+    #[test]
+    fn test_from_many() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+        let channel = &idsp_file.channels[0];
+        let pcm = decode_gc_adpcm(&channel.audio, &channel.metadata.coefficients);
+
+        assert_eq!(Coefficients::from_many(&[&pcm]), Coefficients::from(&pcm));
+
+        let (first, second) = pcm.split_at(pcm.len() / 2);
+        let merged = Coefficients::from_many(&[first, second]);
+        assert!(merged.is_stable());
+        assert_ne!(merged, Coefficients::from(first));
+        assert_ne!(merged, Coefficients::from(second));
+    }
+
+    #[test]
+    fn test_degenerate_inputs() {
+        let empty: &[i16] = &[];
+        let silent = [0i16; 1008];
+
+        for input in &[empty, &silent[..]] {
+            let (coefficients, diagnostics) = Coefficients::from_many_with_diagnostics(&[input]);
+
+            assert!(diagnostics.used_fallback());
+            assert_eq!(coefficients, Coefficients::default());
+            assert_eq!(Coefficients::from(input), coefficients);
+        }
+
+        let (_, diagnostics) = Coefficients::from_many_with_diagnostics(&[&silent]);
+        assert_eq!(diagnostics.frame_count, 72);
+        assert_eq!(diagnostics.rejected_low_energy, 72);
+
+        // Only the step up from silence is usable, which yields a hold-last-sample predictor.
+        let constant = [1234i16; 1008];
+        let clipped = [i16::MAX; 1008];
+        let mut hold = [0i16; 16];
+        hold[0] = 2047;
+
+        for input in &[constant, clipped] {
+            let (coefficients, diagnostics) = Coefficients::from_many_with_diagnostics(&[input]);
+
+            assert_eq!(diagnostics.records_accepted, 1);
+            assert_eq!(diagnostics.rejected_singular, 71);
+            assert_eq!(coefficients, Coefficients::new(hold));
+            assert_eq!(diagnostics.stabilized_predictors, vec![0]);
+            assert!(coefficients.is_stable());
+
+            // The nudged predictor decays slightly each sample, which the residual makes up for.
+            let decoded = decode_gc_adpcm(&encode_gc_adpcm(input, &coefficients), &coefficients);
+            for (&d, &i) in decoded[28..input.len()].iter().zip(&input[28..]) {
+                assert!((i32::from(d) - i32::from(i)).abs() <= 4);
+            }
         }
+
+        let short = [100i16, -2000, 3000, -50, 7];
+        let (coefficients, diagnostics) = Coefficients::from_many_with_diagnostics(&[&short]);
+        assert_eq!(diagnostics.frame_count, 1);
+        assert_eq!(diagnostics.records_accepted, 1);
+        assert!(coefficients.is_stable());
+    }
+
+    #[test]
+    fn test_diagnostics_count_every_frame() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+        let channel = &idsp_file.channels[0];
+        let pcm = decode_gc_adpcm(&channel.audio, &channel.metadata.coefficients);
+
+        let (coefficients, diagnostics) = Coefficients::from_many_with_diagnostics(&[&pcm]);
+
+        assert_eq!(coefficients, Coefficients::from(&pcm));
+        assert!(coefficients.is_stable());
+        assert!(!diagnostics.used_fallback());
+        assert_eq!(
+            diagnostics.records_accepted
+                + diagnostics.rejected_low_energy
+                + diagnostics.rejected_singular
+                + diagnostics.rejected_unstable,
+            diagnostics.frame_count
+        );
+    }
+
+    #[test]
+    fn test_predictor_stability() {
+        let mut coefficients = Coefficients::new(COEFS);
+        assert_eq!(coefficients.predictor(1), (3980, -2016));
+        assert!(coefficients.is_stable());
+
+        coefficients.coefs[2] = 4096;
+        coefficients.coefs[9] = 2048;
+        assert_eq!(coefficients.unstable_predictors().collect::<Vec<_>>(), vec![1, 4]);
+        assert!(!coefficients.is_stable());
     }
 }
@@ -0,0 +1,181 @@
+//! A common interface over the GC-ADPCM containers this crate reads and writes.
+//!
+//! Every container converts to and from [`GcAdpcmAudio`], which holds the encoded
+//! [`Channel`]s and nothing format specific, so converting between formats never decodes
+//! the audio.
+
+use crate::idsp::{
+    BlockPadding, Channel, IdspContainer, IdspDialect, CHANNEL_INFO_SIZE, DEFAULT_INTERLEAVE_SIZE,
+    STREAM_INFO_SIZE,
+};
+use alloc::vec::Vec;
+
+pub trait AudioContainer {
+    fn sample_rate(&self) -> usize;
+    fn sample_count(&self) -> usize;
+    /// `(start, end)` in samples, with `end` exclusive, or `None` if the audio doesn't loop.
+    fn loop_points(&self) -> Option<(usize, usize)>;
+    fn channels(&self) -> &[Channel];
+
+    fn channel_count(&self) -> usize {
+        self.channels().len()
+    }
+
+    fn decode_channel(&self, index: usize) -> Option<Vec<i16>> {
+        self.channels().get(index).map(Channel::decode)
+    }
+
+    fn to_gc_adpcm(&self) -> GcAdpcmAudio {
+        GcAdpcmAudio {
+            sample_rate: self.sample_rate(),
+            sample_count: self.sample_count(),
+            loop_points: self.loop_points(),
+            channels: self.channels().to_vec(),
+        }
+    }
+
+    /// Converts to another container format, keeping the encoded audio as is.
+    fn convert<T: From<GcAdpcmAudio>>(&self) -> T
+    where
+        Self: Sized,
+    {
+        T::from(self.to_gc_adpcm())
+    }
+}
+
+/// GC-ADPCM audio without any container specifics.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GcAdpcmAudio {
+    pub sample_rate: usize,
+    pub sample_count: usize,
+    pub loop_points: Option<(usize, usize)>,
+    pub channels: Vec<Channel>,
+}
+
+impl AudioContainer for GcAdpcmAudio {
+    fn sample_rate(&self) -> usize {
+        self.sample_rate
+    }
+
+    fn sample_count(&self) -> usize {
+        self.sample_count
+    }
+
+    fn loop_points(&self) -> Option<(usize, usize)> {
+        self.loop_points
+    }
+
+    fn channels(&self) -> &[Channel] {
+        &self.channels
+    }
+}
+
+impl AudioContainer for IdspContainer {
+    fn sample_rate(&self) -> usize {
+        self.sample_rate
+    }
+
+    fn sample_count(&self) -> usize {
+        self.sample_count
+    }
+
+    fn loop_points(&self) -> Option<(usize, usize)> {
+        if self.looping {
+            Some((self.loop_start, self.loop_end))
+        } else {
+            None
+        }
+    }
+
+    fn channels(&self) -> &[Channel] {
+        &self.channels
+    }
+}
+
+impl From<IdspContainer> for GcAdpcmAudio {
+    fn from(container: IdspContainer) -> Self {
+        GcAdpcmAudio {
+            sample_rate: container.sample_rate,
+            sample_count: container.sample_count,
+            loop_points: container.loop_points(),
+            channels: container.channels,
+        }
+    }
+}
+
+impl From<GcAdpcmAudio> for IdspContainer {
+    fn from(audio: GcAdpcmAudio) -> Self {
+        let (loop_start, loop_end) = audio.loop_points.unwrap_or((0, 0));
+
+        IdspContainer {
+            looping: audio.loop_points.is_some(),
+            channel_count: audio.channels.len(),
+            sample_rate: audio.sample_rate,
+            loop_start,
+            loop_end,
+            sample_count: audio.sample_count,
+            interleave_size: DEFAULT_INTERLEAVE_SIZE,
+            padding: BlockPadding::FullBlock,
+            header_size: STREAM_INFO_SIZE,
+            channel_info_size: CHANNEL_INFO_SIZE,
+            dialect: IdspDialect::BigEndian,
+            channels: audio.channels,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        brstm::{read_brstm_bytes, write_brstm_bytes},
+        dsp::{read_dsp_channels, write_dsp_channels},
+        idsp::read_idsp_bytes,
+    };
+
+    #[test]
+    fn test_idsp_conversion() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+
+        assert_eq!(idsp_file.channel_count(), 1);
+        assert_eq!(idsp_file.loop_points(), None);
+        assert_eq!(idsp_file.decode_channel(0), Some(idsp_file.channels[0].decode()));
+        assert_eq!(idsp_file.decode_channel(1), None);
+
+        let audio = GcAdpcmAudio::from(idsp_file.clone());
+        assert_eq!(audio, idsp_file.to_gc_adpcm());
+
+        let converted: IdspContainer = audio.convert();
+        assert_eq!(converted, idsp_file);
+        assert_eq!(IdspContainer::from(audio), idsp_file);
+    }
+
+    #[test]
+    fn test_brstm_and_dsp_conversion() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+        let audio = idsp_file.to_gc_adpcm();
+
+        let brstm = read_brstm_bytes(&write_brstm_bytes(&idsp_file).unwrap()).unwrap();
+        let dsp_files = write_dsp_channels(&brstm);
+        let dsp_files: Vec<&[u8]> = dsp_files.iter().map(|file| file.as_slice()).collect();
+        let dsp = read_dsp_channels(&dsp_files).unwrap();
+
+        for converted in &[&brstm, &dsp] {
+            assert_eq!(converted.sample_rate(), audio.sample_rate);
+            assert_eq!(converted.sample_count(), audio.sample_count);
+            assert_eq!(converted.loop_points(), None);
+            assert_eq!(converted.decode_channel(0), idsp_file.decode_channel(0));
+            assert_eq!(
+                converted.channels()[0].metadata.coefficients,
+                audio.channels[0].metadata.coefficients
+            );
+        }
+
+        // Back in an IDSP container the blocking comes from the defaults.
+        let idsp: IdspContainer = dsp.convert();
+        assert_eq!(idsp.interleave_size, DEFAULT_INTERLEAVE_SIZE);
+        assert_eq!(idsp.decode_channels(), idsp_file.decode_channels());
+    }
+}
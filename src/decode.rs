@@ -1,57 +1,76 @@
 use crate::{
+    coefficients::Coefficients,
+    idsp::GcAdpcmContext,
     math::{
         byte_count_to_sample_count, clamp_16, high_nibble, high_nibble_signed, low_nibble,
-        low_nibble_signed, DivideByRoundUp,
+        low_nibble_signed,
     },
-    CodecParameters, SAMPLES_PER_FRAME,
+    BYTES_PER_FRAME, SAMPLES_PER_FRAME,
 };
+use alloc::{vec, vec::Vec};
 
-pub fn decode_gc_adpcm(adpcm: &[u8], coefficients: &[i16]) -> Vec<i16> {
-    let config = CodecParameters {
-        sample_count: byte_count_to_sample_count(adpcm.len()),
-        history_1: 0,
-        history_2: 0,
-    };
+pub fn decode_gc_adpcm(adpcm: &[u8], coefficients: &Coefficients) -> Vec<i16> {
+    let mut pcm = vec![0; byte_count_to_sample_count(adpcm.len())];
+    decode_gc_adpcm_into(adpcm, coefficients, &mut GcAdpcmContext::default(), &mut pcm);
 
-    let mut pcm = vec![0; config.sample_count];
+    pcm
+}
 
-    if config.sample_count == 0 {
-        return pcm;
-    }
+/// Decodes `adpcm` into `pcm` without allocating, returning the number of samples written.
+///
+/// Decoding stops when either buffer runs out. The history in `context` seeds the first
+/// frame and is updated afterwards, so a stream can be decoded in chunks as long as each
+/// chunk of `adpcm` starts on a frame boundary.
+pub fn decode_gc_adpcm_into(
+    adpcm: &[u8],
+    coefficients: &Coefficients,
+    context: &mut GcAdpcmContext,
+    pcm: &mut [i16],
+) -> usize {
+    decode_gc_adpcm_from(adpcm, coefficients, context, 0, pcm)
+}
 
-    let frame_count = config.sample_count.divide_by_round_up(SAMPLES_PER_FRAME);
-    let mut current_sample = 0;
+/// Like [`decode_gc_adpcm_into`], but starting at `start_sample`, which can be in the middle
+/// of a frame. `context` holds the two samples before it, as in a loop context.
+pub fn decode_gc_adpcm_from(
+    adpcm: &[u8],
+    coefficients: &Coefficients,
+    context: &mut GcAdpcmContext,
+    start_sample: usize,
+    pcm: &mut [i16],
+) -> usize {
+    let sample_count =
+        byte_count_to_sample_count(adpcm.len()).saturating_sub(start_sample).min(pcm.len());
+    let end_sample = start_sample + sample_count;
+    let mut current_sample = start_sample;
     let mut out_index = 0;
-    let mut in_index = 0;
-    let mut hist_1 = config.history_1;
-    let mut hist_2 = config.history_2;
+    let mut hist_1 = context.hist_1;
+    let mut hist_2 = context.hist_2;
 
-    for _i in 0..frame_count {
-        let predictor_scale: u8 = adpcm[in_index];
-        in_index += 1;
+    while current_sample < end_sample {
+        let frame = &adpcm[current_sample / SAMPLES_PER_FRAME * BYTES_PER_FRAME..];
+        let predictor_scale: u8 = frame[0];
 
-        let scale: i32 = (1 << low_nibble(predictor_scale)) as i32 * 2048;
-        let predictor: i32 = high_nibble(predictor_scale) as i32;
-        let coef_1: i16 = coefficients[predictor as usize * 2];
-        let coef_2: i16 = coefficients[predictor as usize * 2 + 1];
+        let scale: i32 = (1 << low_nibble(predictor_scale)) * 2048;
+        let predictor: usize = high_nibble(predictor_scale) as usize;
+        let (coef_1, coef_2): (i16, i16) = coefficients.predictor(predictor);
 
-        let samples_to_read: i32 =
-            SAMPLES_PER_FRAME.min(config.sample_count - current_sample) as i32;
+        let first = current_sample % SAMPLES_PER_FRAME;
+        let last = SAMPLES_PER_FRAME.min(first + end_sample - current_sample);
 
-        for s in 0..samples_to_read {
+        for s in first..last {
+            let byte = frame[1 + s / 2];
             let adpcm_sample: i32 = if s % 2 == 0 {
-                high_nibble_signed(adpcm[in_index]) as i32
+                high_nibble_signed(byte) as i32
             } else {
-                let sample = low_nibble_signed(adpcm[in_index]);
-                in_index += 1;
-                sample as i32
+                low_nibble_signed(byte) as i32
             };
 
             let distance: i32 = scale * adpcm_sample;
             // TODO(bschwind) - should these coefficients be casted here?
             let predicted_sample: i32 =
                 coef_1 as i32 * hist_1 as i32 + coef_2 as i32 * hist_2 as i32;
-            let corrected_sample: i32 = predicted_sample as i32 + distance;
+            let corrected_sample: i32 = predicted_sample + distance;
             let scaled_sample: i32 = (corrected_sample + 1024) >> 11;
 
             let clamped_sample: i16 = clamp_16(scaled_sample);
@@ -61,9 +80,67 @@ pub fn decode_gc_adpcm(adpcm: &[u8], coefficients: &[i16]) -> Vec<i16> {
 
             pcm[out_index] = clamped_sample;
             out_index += 1;
-            current_sample += 1;
         }
+
+        current_sample += last - first;
     }
 
-    pcm
+    context.hist_1 = hist_1;
+    context.hist_2 = hist_2;
+
+    sample_count
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        decode::{decode_gc_adpcm, decode_gc_adpcm_from, decode_gc_adpcm_into},
+        idsp::{read_idsp_bytes, GcAdpcmContext},
+        BYTES_PER_FRAME, SAMPLES_PER_FRAME,
+    };
+
+    #[test]
+    fn test_decode_into_in_chunks() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+        let channel = &idsp_file.channels[0];
+        let coefficients = &channel.metadata.coefficients;
+
+        let expected = decode_gc_adpcm(&channel.audio, &channel.metadata.coefficients);
+
+        let mut context = GcAdpcmContext::default();
+        let mut pcm = vec![0; expected.len()];
+        let mut decoded = 0;
+
+        for chunk in channel.audio.chunks(BYTES_PER_FRAME * 3) {
+            let out = &mut pcm[decoded..(decoded + SAMPLES_PER_FRAME * 3).min(expected.len())];
+            decoded += decode_gc_adpcm_into(chunk, coefficients, &mut context, out);
+        }
+
+        assert_eq!(decoded, expected.len());
+        assert_eq!(pcm, expected);
+    }
+
+    #[test]
+    fn test_decode_from_mid_frame() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+        let channel = &idsp_file.channels[0];
+        let expected = decode_gc_adpcm(&channel.audio, &channel.metadata.coefficients);
+
+        let start = 1000;
+        let mut context = GcAdpcmContext::at_sample(&channel.audio, &expected, start);
+        let mut pcm = vec![0; 500];
+        let decoded = decode_gc_adpcm_from(
+            &channel.audio,
+            &channel.metadata.coefficients,
+            &mut context,
+            start,
+            &mut pcm,
+        );
+
+        assert_eq!(decoded, 500);
+        assert_eq!(pcm, expected[start..start + 500]);
+        assert_eq!((context.hist_1, context.hist_2), (expected[1499], expected[1498]));
+    }
 }
@@ -0,0 +1,170 @@
+//! Nintendo's standard `.dsp` files: a single GC-ADPCM channel.
+//!
+//! A DSP file is the same big-endian channel header IDSP files use, padded to 0x60 bytes and
+//! followed by the audio. There is no magic number, so [`is_plausible_dsp`] checks the header
+//! fields against each other instead.
+
+use crate::{
+    container::{AudioContainer, GcAdpcmAudio},
+    idsp::{Channel, ChannelMetadata, DecodeError, IdspDialect},
+    math::{nibble_address_to_sample, sample_count_to_byte_count, sample_count_to_nibble_count},
+};
+use alloc::{vec, vec::Vec};
+use bytes::BytesMut;
+#[cfg(feature = "std")]
+use std::{fs::File, io::Read, path::Path};
+
+const DSP_HEADER_SIZE: usize = 0x60;
+const FORMAT_OFFSET: usize = 0x0E;
+const LOOP_FLAG_OFFSET: usize = 0x0C;
+/// Wider than any console plays back, to reject implausible headers.
+const MAX_SAMPLE_RATE: usize = 96000;
+
+#[cfg(feature = "std")]
+pub fn read_dsp<P: AsRef<Path>>(file_path: P) -> Result<GcAdpcmAudio, DecodeError> {
+    let mut file = File::open(file_path)?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+
+    read_dsp_bytes(&bytes)
+}
+
+pub fn read_dsp_bytes(bytes: &[u8]) -> Result<GcAdpcmAudio, DecodeError> {
+    if !is_plausible_dsp(bytes) {
+        return Err(DecodeError::InvalidHeader);
+    }
+
+    let metadata = ChannelMetadata::read(IdspDialect::BigEndian, &mut &bytes[..])?;
+    let byte_count = sample_count_to_byte_count(metadata.sample_count);
+    let audio = bytes[DSP_HEADER_SIZE..DSP_HEADER_SIZE + byte_count].to_vec();
+
+    // The end address points at the last nibble of the loop, so the exclusive end is one past.
+    let loop_points = if metadata.looping {
+        Some((
+            nibble_address_to_sample(metadata.start_address),
+            nibble_address_to_sample(metadata.end_address) + 1,
+        ))
+    } else {
+        None
+    };
+
+    Ok(GcAdpcmAudio {
+        sample_rate: metadata.sample_rate,
+        sample_count: metadata.sample_count,
+        loop_points,
+        channels: vec![Channel { metadata, audio }],
+    })
+}
+
+/// Writes `channel` out as a DSP file. Multi-channel audio is stored as one file per channel.
+pub fn write_dsp_bytes(channel: &Channel) -> Vec<u8> {
+    let byte_count = sample_count_to_byte_count(channel.metadata.sample_count);
+
+    let mut bytes = BytesMut::with_capacity(DSP_HEADER_SIZE + byte_count);
+    channel.metadata.write(IdspDialect::BigEndian, &mut bytes);
+    bytes.resize(DSP_HEADER_SIZE, 0);
+    bytes.extend_from_slice(&channel.audio[..byte_count.min(channel.audio.len())]);
+
+    bytes.to_vec()
+}
+
+/// Writes every channel of `audio` out as its own DSP file.
+pub fn write_dsp_channels<C: AudioContainer + ?Sized>(audio: &C) -> Vec<Vec<u8>> {
+    audio.channels().iter().map(write_dsp_bytes).collect()
+}
+
+/// Reads one DSP file per channel back into a single stream. Every file has to have the same
+/// sample rate, length and loop.
+pub fn read_dsp_channels(files: &[&[u8]]) -> Result<GcAdpcmAudio, DecodeError> {
+    let mut files = files.iter().map(|bytes| read_dsp_bytes(bytes));
+    let mut audio = files.next().ok_or(DecodeError::InvalidHeader)??;
+
+    for channel_audio in files {
+        let channel_audio = channel_audio?;
+        if channel_audio.sample_rate != audio.sample_rate
+            || channel_audio.sample_count != audio.sample_count
+            || channel_audio.loop_points != audio.loop_points
+        {
+            return Err(DecodeError::InvalidHeader);
+        }
+
+        audio.channels.extend(channel_audio.channels);
+    }
+
+    Ok(audio)
+}
+
+/// Whether `bytes` look like a DSP file: the nibble count has to match the sample count, the
+/// reserved fields have to be zero, the audio has to fit and the coefficients can't all be
+/// zero.
+pub fn is_plausible_dsp(bytes: &[u8]) -> bool {
+    if bytes.len() < DSP_HEADER_SIZE {
+        return false;
+    }
+
+    let metadata = match ChannelMetadata::read(IdspDialect::BigEndian, &mut &bytes[..]) {
+        Ok(metadata) => metadata,
+        Err(_) => return false,
+    };
+
+    let loop_flag = u16::from_be_bytes([bytes[LOOP_FLAG_OFFSET], bytes[LOOP_FLAG_OFFSET + 1]]);
+    let format = u16::from_be_bytes([bytes[FORMAT_OFFSET], bytes[FORMAT_OFFSET + 1]]);
+    let audio_len = bytes.len() - DSP_HEADER_SIZE;
+
+    // Bounding the sample count by the audio length first keeps the arithmetic below from
+    // overflowing on garbage.
+    metadata.sample_count > 0
+        && metadata.sample_count <= audio_len * 2
+        && metadata.nibble_count == sample_count_to_nibble_count(metadata.sample_count)
+        && sample_count_to_byte_count(metadata.sample_count) <= audio_len
+        && (1..=MAX_SAMPLE_RATE).contains(&metadata.sample_rate)
+        && loop_flag <= 1
+        && format == 0
+        && (!metadata.looping
+            || (metadata.start_address < metadata.end_address
+                && metadata.end_address < metadata.nibble_count + 2))
+        && metadata.coefficients.iter().any(|&coef| coef != 0)
+        // The predictor index of the first frame header.
+        && bytes[DSP_HEADER_SIZE] >> 4 < 8
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::idsp::read_idsp_bytes;
+
+    #[test]
+    fn test_dsp_roundtrip() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let mut idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+        idsp_file.set_loop_points(Some((1000, 40000)), false).unwrap();
+
+        let dsp_bytes = write_dsp_bytes(&idsp_file.channels[0]);
+        assert!(is_plausible_dsp(&dsp_bytes));
+
+        let audio = read_dsp_bytes(&dsp_bytes).unwrap();
+        assert_eq!(audio.loop_points, Some((1000, 40000)));
+        assert_eq!(audio.sample_rate, idsp_file.sample_rate);
+        assert_eq!(audio.channels[0], idsp_file.channels[0]);
+        assert_eq!(write_dsp_bytes(&audio.channels[0]), dsp_bytes);
+    }
+
+    #[test]
+    fn test_implausible_dsp() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+        let dsp_bytes = write_dsp_bytes(&idsp_file.channels[0]);
+
+        assert!(!is_plausible_dsp(&dsp_bytes[..DSP_HEADER_SIZE + 100]));
+        assert!(!is_plausible_dsp(idsp_bytes));
+        assert!(!is_plausible_dsp(&[0; 0x1000]));
+        assert!(!is_plausible_dsp(&[0xFF; 0x1000]));
+
+        let mut bad_nibble_count = dsp_bytes.clone();
+        bad_nibble_count[7] ^= 1;
+        assert!(!is_plausible_dsp(&bad_nibble_count));
+        assert!(read_dsp_bytes(&bad_nibble_count).is_err());
+
+        assert_eq!(read_dsp_bytes(&dsp_bytes).unwrap().decode_channel(0).unwrap().len(), 47035);
+    }
+}
@@ -1,15 +1,21 @@
 use crate::{
-    math::{clamp_16, clamp_4, combine_nibbles, sample_count_to_byte_count, DivideByRoundUp},
+    coefficients::Coefficients,
+    idsp::GcAdpcmContext,
+    math::{
+        byte_count_to_sample_count, clamp_16, clamp_4, combine_nibbles, sample_count_to_byte_count,
+        DivideByRoundUp,
+    },
     CodecParameters, BYTES_PER_FRAME, SAMPLES_PER_FRAME,
 };
-use std::ops::{Deref, DerefMut};
+use alloc::{vec, vec::Vec};
+use core::ops::{Deref, DerefMut};
 
 struct AdpcmEncodeBuffers {
-    coefficients: Vec<Vec<i16>>,
-    pcm_out: Vec<Vec<i32>>,
-    adpcm_out: Vec<Vec<i32>>,
-    scale: Vec<i32>,
-    total_distance: Vec<f64>,
+    coefficients: [[i16; 2]; 8],
+    pcm_out: [[i32; 16]; 8],
+    adpcm_out: [[i32; 14]; 8],
+    scale: [i32; 8],
+    total_distance: [f64; 8],
 }
 
 pub struct GcAdpcmStream {
@@ -33,36 +39,53 @@ impl DerefMut for GcAdpcmStream {
 
 impl AdpcmEncodeBuffers {
     fn new() -> Self {
-        let mut coefficients = vec![vec![]; 8];
-        let mut pcm_out = vec![vec![]; 8];
-        let mut adpcm_out = vec![vec![]; 8];
-        let scale = vec![0; 8];
-        let total_distance = vec![0.0; 8];
-
-        for i in 0..8 {
-            pcm_out[i] = vec![0; 16];
-            adpcm_out[i] = vec![0; 14];
-            coefficients[i] = vec![0; 2];
+        Self {
+            coefficients: [[0; 2]; 8],
+            pcm_out: [[0; 16]; 8],
+            adpcm_out: [[0; 14]; 8],
+            scale: [0; 8],
+            total_distance: [0.0; 8],
         }
-
-        Self { coefficients, pcm_out, adpcm_out, scale, total_distance }
     }
 }
 
-pub fn encode_gc_adpcm(pcm: &[i16], coefficients: &[i16]) -> GcAdpcmStream {
-    let config = CodecParameters { sample_count: pcm.len(), history_1: 0, history_2: 0 };
-
+pub fn encode_gc_adpcm(pcm: &[i16], coefficients: &Coefficients) -> GcAdpcmStream {
     let sample_count = pcm.len();
     let mut adpcm = vec![0; sample_count_to_byte_count(sample_count)];
 
-    let mut pcm_buffer = vec![0i16; 2 + SAMPLES_PER_FRAME];
-    let mut adpcm_buffer = vec![0u8; BYTES_PER_FRAME];
+    encode_gc_adpcm_into(pcm, coefficients, &mut GcAdpcmContext::default(), &mut adpcm);
+
+    GcAdpcmStream { sample_count, data: adpcm }
+}
+
+/// Encodes `pcm` into `adpcm` without allocating, returning the number of bytes written.
+///
+/// Encoding stops when either buffer runs out. The history in `context` seeds the first
+/// frame and is replaced with the last two reconstructed samples, so a stream can be
+/// encoded in chunks as long as every chunk but the last is a whole number of frames.
+pub fn encode_gc_adpcm_into(
+    pcm: &[i16],
+    coefficients: &Coefficients,
+    context: &mut GcAdpcmContext,
+    adpcm: &mut [u8],
+) -> usize {
+    let config = CodecParameters {
+        sample_count: pcm.len().min(byte_count_to_sample_count(adpcm.len())),
+        history_1: context.hist_1,
+        history_2: context.hist_2,
+    };
+
+    let sample_count = config.sample_count;
+
+    let mut pcm_buffer = [0i16; 2 + SAMPLES_PER_FRAME];
+    let mut adpcm_buffer = [0u8; BYTES_PER_FRAME];
 
     pcm_buffer[0] = config.history_2;
     pcm_buffer[1] = config.history_1;
 
     let frame_count = sample_count.divide_by_round_up(SAMPLES_PER_FRAME);
     let mut buffers = AdpcmEncodeBuffers::new();
+    let mut bytes_written = 0;
 
     for frame in 0..frame_count {
         let samples_to_copy = (sample_count - frame * SAMPLES_PER_FRAME).min(SAMPLES_PER_FRAME);
@@ -80,31 +103,35 @@ pub fn encode_gc_adpcm(pcm: &[i16], coefficients: &[i16]) -> GcAdpcmStream {
             &mut pcm_buffer,
             SAMPLES_PER_FRAME,
             &mut adpcm_buffer,
-            &coefficients,
+            coefficients,
             &mut buffers,
         );
         let bytes_to_copy = sample_count_to_byte_count(samples_to_copy);
         let dst_index = frame * BYTES_PER_FRAME;
         adpcm[dst_index..(dst_index + bytes_to_copy)]
             .copy_from_slice(&adpcm_buffer[0..bytes_to_copy]);
+        bytes_written = dst_index + bytes_to_copy;
+
+        context.hist_2 = pcm_buffer[samples_to_copy];
+        context.hist_1 = pcm_buffer[samples_to_copy + 1];
 
         pcm_buffer[0] = pcm_buffer[14];
         pcm_buffer[1] = pcm_buffer[15];
     }
 
-    GcAdpcmStream { sample_count, data: adpcm }
+    bytes_written
 }
 
 fn dsp_encode_frame(
     pcm_in_out: &mut [i16],
     sample_count: usize,
     adpcm_out: &mut [u8],
-    coefficients_in: &[i16],
+    coefficients_in: &Coefficients,
     b: &mut AdpcmEncodeBuffers,
 ) {
     for i in 0..8 {
-        b.coefficients[i][0] = coefficients_in[i * 2];
-        b.coefficients[i][1] = coefficients_in[i * 2 + 1];
+        let (coef_1, coef_2) = coefficients_in.predictor(i);
+        b.coefficients[i] = [coef_1, coef_2];
     }
 
     for i in 0..8 {
@@ -180,7 +207,7 @@ fn dsp_encode_coefficient(
 
     // Use the maximum distance of the encoded frame to find a scale that will fit the current frame.
     *scale_power = 0;
-    while *scale_power <= 12 && (max_distance > 7 || max_distance < -8) {
+    while *scale_power <= 12 && !(-8..=7).contains(&max_distance) {
         max_distance /= 2;
         *scale_power += 1;
     }
@@ -195,8 +222,8 @@ fn dsp_encode_coefficient(
 
         for s in 0..sample_count {
             let input_sample: i32 = pcm_in[s + 2] as i32 * 2048;
-            let predicted_sample = pcm_out[s] * coefficients[1] as i32
-                + pcm_out[s + 1] as i32 * coefficients[0] as i32;
+            let predicted_sample =
+                pcm_out[s] * coefficients[1] as i32 + pcm_out[s + 1] * coefficients[0] as i32;
             let distance = input_sample - predicted_sample;
 
             let unclamped_adpcm_sample = if distance > 0 {
@@ -225,7 +252,7 @@ fn dsp_encode_coefficient(
 
             // Decode sample to use as history
             let decoded_distance: i32 = adpcm_sample as i32 * scale;
-            let corrected_sample = predicted_sample + decoded_distance as i32;
+            let corrected_sample = predicted_sample + decoded_distance;
             let scaled_sample = (corrected_sample + 1024) >> 11;
 
             // Clamp and store
@@ -258,8 +285,11 @@ fn dsp_encode_coefficient(
 #[cfg(test)]
 mod test {
     use crate::{
-        coefficients::Coefficients, decode::decode_gc_adpcm, encode::encode_gc_adpcm,
-        idsp::read_idsp_bytes,
+        coefficients::Coefficients,
+        decode::decode_gc_adpcm,
+        encode::{encode_gc_adpcm, encode_gc_adpcm_into},
+        idsp::{read_idsp_bytes, GcAdpcmContext},
+        BYTES_PER_FRAME, SAMPLES_PER_FRAME,
     };
     use wav::{BitDepth, Header};
 
@@ -299,7 +329,8 @@ mod test {
         let header =
             Header::new(1, idsp_file.channels.len() as u16, idsp_file.sample_rate as u32, 16);
 
-        let mut output_file = std::fs::File::create("roundtrip.wav").unwrap();
+        let mut output_file =
+            std::fs::File::create(std::env::temp_dir().join("roundtrip.wav")).unwrap();
         wav::write(header, BitDepth::Sixteen(decoded_again), &mut output_file).unwrap();
     }
 
@@ -342,4 +373,27 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn test_encode_into_in_chunks() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+        let channel = &idsp_file.channels[0];
+        let coefficients = &channel.metadata.coefficients;
+
+        let decoded = decode_gc_adpcm(&channel.audio, &channel.metadata.coefficients);
+        let expected = encode_gc_adpcm(&decoded, &channel.metadata.coefficients);
+
+        let mut context = GcAdpcmContext::default();
+        let mut adpcm = vec![0; expected.len()];
+        let mut written = 0;
+
+        for chunk in decoded.chunks(SAMPLES_PER_FRAME * 5) {
+            let out = &mut adpcm[written..(written + BYTES_PER_FRAME * 5).min(expected.len())];
+            written += encode_gc_adpcm_into(chunk, coefficients, &mut context, out);
+        }
+
+        assert_eq!(written, expected.len());
+        assert_eq!(adpcm, *expected);
+    }
 }
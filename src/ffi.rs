@@ -0,0 +1,325 @@
+//! A C interface to reading, decoding and encoding IDSP files, enabled by the `ffi` feature.
+//!
+//! The header in `include/idsp.h` is generated by cbindgen. Build with `IDSP_UPDATE_HEADER=1`
+//! to update it after changing this module.
+//!
+//! Containers and buffers returned by these functions are owned by the caller, who has to
+//! pass them back to [`idsp_free`] and [`idsp_buffer_free`]. Panics never cross into C:
+//! functions returning a status report them as `IDSP_STATUS_PANIC`, and the others return
+//! zero or false.
+
+use crate::{
+    builder::IdspBuilder,
+    idsp::{read_idsp_bytes, write_idsp_bytes, DecodeError, EncodeError, IdspContainer},
+};
+use core::{ptr, slice};
+use std::panic::{self, AssertUnwindSafe};
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IdspStatus {
+    Ok,
+    NullPointer,
+    Io,
+    InvalidHeader,
+    InvalidAudioLength,
+    UnsupportedFormat,
+    InvalidChannelCount,
+    InvalidLoopPoints,
+    InvalidBlockSize,
+    MismatchedChannels,
+    /// The channel index is out of range.
+    InvalidChannel,
+    /// The caller's buffer can't hold the result.
+    BufferTooSmall,
+    InvalidHeaderSize,
+    InvalidSampleRate,
+    InvalidIndex,
+    /// A bug in this library, caught before it could unwind into the caller.
+    Panic,
+}
+
+impl From<DecodeError> for IdspStatus {
+    fn from(err: DecodeError) -> Self {
+        match err {
+            DecodeError::Io(_) => IdspStatus::Io,
+            DecodeError::InvalidHeader => IdspStatus::InvalidHeader,
+            DecodeError::InvalidAudioLength => IdspStatus::InvalidAudioLength,
+            DecodeError::UnsupportedFormat => IdspStatus::UnsupportedFormat,
+        }
+    }
+}
+
+impl From<EncodeError> for IdspStatus {
+    fn from(err: EncodeError) -> Self {
+        match err {
+            EncodeError::InvalidChannelCount => IdspStatus::InvalidChannelCount,
+            EncodeError::InvalidLoopPoints => IdspStatus::InvalidLoopPoints,
+            EncodeError::InvalidBlockSize => IdspStatus::InvalidBlockSize,
+            EncodeError::InvalidAudioLength => IdspStatus::InvalidAudioLength,
+            EncodeError::MismatchedChannels => IdspStatus::MismatchedChannels,
+            EncodeError::InvalidHeaderSize => IdspStatus::InvalidHeaderSize,
+            EncodeError::InvalidSampleRate => IdspStatus::InvalidSampleRate,
+            EncodeError::InvalidIndex => IdspStatus::InvalidIndex,
+        }
+    }
+}
+
+/// Runs `f`, returning `fallback` if it panics, since unwinding into C is undefined.
+fn catch_panic<T>(fallback: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(fallback)
+}
+
+/// Bytes allocated by this library.
+#[repr(C)]
+pub struct IdspBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+impl From<Vec<u8>> for IdspBuffer {
+    fn from(bytes: Vec<u8>) -> Self {
+        let len = bytes.len();
+        let data = Box::into_raw(bytes.into_boxed_slice()) as *mut u8;
+
+        IdspBuffer { data, len }
+    }
+}
+
+/// Reads the IDSP file in `bytes[..len]`, storing the container in `out` on success.
+///
+/// # Safety
+///
+/// `bytes` must point to `len` readable bytes and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn idsp_open(
+    bytes: *const u8,
+    len: usize,
+    out: *mut *mut IdspContainer,
+) -> IdspStatus {
+    catch_panic(IdspStatus::Panic, || {
+        if bytes.is_null() || out.is_null() {
+            return IdspStatus::NullPointer;
+        }
+
+        match read_idsp_bytes(slice::from_raw_parts(bytes, len)) {
+            Ok(container) => {
+                *out = Box::into_raw(Box::new(container));
+                IdspStatus::Ok
+            },
+            Err(err) => err.into(),
+        }
+    })
+}
+
+/// Frees a container from [`idsp_open`]. Null is ignored.
+///
+/// # Safety
+///
+/// `container` must come from [`idsp_open`] and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn idsp_free(container: *mut IdspContainer) {
+    catch_panic((), || {
+        if !container.is_null() {
+            drop(Box::from_raw(container));
+        }
+    })
+}
+
+/// # Safety
+///
+/// `container` must be a live container from [`idsp_open`].
+#[no_mangle]
+pub unsafe extern "C" fn idsp_channel_count(container: *const IdspContainer) -> usize {
+    catch_panic(0, || container.as_ref().map_or(0, |container| container.channels.len()))
+}
+
+/// # Safety
+///
+/// `container` must be a live container from [`idsp_open`].
+#[no_mangle]
+pub unsafe extern "C" fn idsp_sample_count(container: *const IdspContainer) -> usize {
+    catch_panic(0, || container.as_ref().map_or(0, |container| container.sample_count))
+}
+
+/// # Safety
+///
+/// `container` must be a live container from [`idsp_open`].
+#[no_mangle]
+pub unsafe extern "C" fn idsp_sample_rate(container: *const IdspContainer) -> u32 {
+    catch_panic(0, || container.as_ref().map_or(0, |container| container.sample_rate as u32))
+}
+
+/// Returns whether the audio loops, storing the loop points in `start` and `end`, with `end`
+/// exclusive, if it does.
+///
+/// # Safety
+///
+/// `container` must be a live container from [`idsp_open`], and `start` and `end` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn idsp_loop_points(
+    container: *const IdspContainer,
+    start: *mut usize,
+    end: *mut usize,
+) -> bool {
+    catch_panic(false, || match container.as_ref() {
+        Some(container) if container.looping && !start.is_null() && !end.is_null() => {
+            *start = container.loop_start;
+            *end = container.loop_end;
+            true
+        },
+        _ => false,
+    })
+}
+
+/// Decodes `channel` into `pcm`, which has to hold at least [`idsp_sample_count`] samples.
+///
+/// # Safety
+///
+/// `container` must be a live container from [`idsp_open`] and `pcm` must point to `pcm_len`
+/// writable samples.
+#[no_mangle]
+pub unsafe extern "C" fn idsp_decode_channel(
+    container: *const IdspContainer,
+    channel: usize,
+    pcm: *mut i16,
+    pcm_len: usize,
+) -> IdspStatus {
+    catch_panic(IdspStatus::Panic, || {
+        let container = match container.as_ref() {
+            Some(container) if !pcm.is_null() => container,
+            _ => return IdspStatus::NullPointer,
+        };
+
+        let channel = match container.channels.get(channel) {
+            Some(channel) => channel,
+            None => return IdspStatus::InvalidChannel,
+        };
+
+        if pcm_len < container.sample_count {
+            return IdspStatus::BufferTooSmall;
+        }
+
+        let decoded = channel.decode();
+        ptr::copy_nonoverlapping(decoded.as_ptr(), pcm, decoded.len().min(pcm_len));
+
+        IdspStatus::Ok
+    })
+}
+
+/// Encodes `sample_count` frames of interleaved PCM with `channel_count` channels into an
+/// IDSP file, storing it in `out` on success. `loop_end` is exclusive and both loop points
+/// are ignored unless `looping` is set.
+///
+/// # Safety
+///
+/// `pcm` must point to `sample_count * channel_count` readable samples and `out` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn idsp_encode(
+    pcm: *const i16,
+    sample_count: usize,
+    channel_count: usize,
+    sample_rate: u32,
+    looping: bool,
+    loop_start: usize,
+    loop_end: usize,
+    out: *mut IdspBuffer,
+) -> IdspStatus {
+    catch_panic(IdspStatus::Panic, || {
+        if pcm.is_null() || out.is_null() {
+            return IdspStatus::NullPointer;
+        }
+
+        if channel_count == 0 {
+            return IdspStatus::InvalidChannelCount;
+        }
+
+        let pcm = match sample_count.checked_mul(channel_count) {
+            Some(len) => slice::from_raw_parts(pcm, len),
+            None => return IdspStatus::InvalidAudioLength,
+        };
+        let mut builder = IdspBuilder::new(sample_rate as usize);
+        for channel in 0..channel_count {
+            builder =
+                builder.channel(pcm.iter().skip(channel).step_by(channel_count).copied().collect());
+        }
+
+        if looping {
+            builder = builder.loop_points(loop_start, loop_end);
+        }
+
+        match builder.build().and_then(|container| write_idsp_bytes(&container)) {
+            Ok(bytes) => {
+                *out = bytes.into();
+                IdspStatus::Ok
+            },
+            Err(err) => err.into(),
+        }
+    })
+}
+
+/// Frees a buffer from [`idsp_encode`]. Empty buffers are ignored.
+///
+/// # Safety
+///
+/// `buffer` must come from [`idsp_encode`] and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn idsp_buffer_free(buffer: IdspBuffer) {
+    catch_panic((), || {
+        if !buffer.data.is_null() {
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(buffer.data, buffer.len)));
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{path::Path, process::Command};
+
+    fn run(command: &mut Command) {
+        let status = command.status().unwrap();
+        assert!(status.success(), "{:?} failed with {}", command, status);
+    }
+
+    #[test]
+    fn test_catch_panic() {
+        assert_eq!(catch_panic(IdspStatus::Panic, || panic!("bug")), IdspStatus::Panic);
+        assert_eq!(catch_panic(0, || 5usize), 5);
+    }
+
+    #[test]
+    fn test_header_up_to_date() {
+        let generated = include_str!(concat!(env!("OUT_DIR"), "/idsp.h"));
+        let committed = include_str!("../include/idsp.h");
+        assert!(generated == committed, "include/idsp.h is stale, build with IDSP_UPDATE_HEADER=1");
+    }
+
+    /// Builds the crate as a static library and runs `tests/ffi.c` against it and the
+    /// generated header.
+    #[test]
+    fn test_c_program() {
+        let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let target_dir = Path::new(env!("OUT_DIR")).join("ffi");
+        let program = target_dir.join("ffi_test");
+
+        run(Command::new(env!("CARGO"))
+            .args(["rustc", "--quiet", "--lib", "--features", "ffi", "--crate-type", "staticlib"])
+            .arg("--manifest-path")
+            .arg(manifest_dir.join("Cargo.toml"))
+            .arg("--target-dir")
+            .arg(&target_dir));
+
+        run(Command::new(std::env::var("CC").unwrap_or_else(|_| "cc".into()))
+            .arg(manifest_dir.join("tests/ffi.c"))
+            .arg("-I")
+            .arg(manifest_dir.join("include"))
+            .arg(target_dir.join("debug/libidsp.a"))
+            .args(["-lpthread", "-ldl", "-lm", "-o"])
+            .arg(&program));
+
+        run(Command::new(&program).arg(manifest_dir.join("test_files/13.idsp")));
+    }
+}
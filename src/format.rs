@@ -0,0 +1,139 @@
+//! Detecting which format a file is in, so any supported file loads through one entry point.
+
+use crate::{
+    brstm::{read_brstm_bytes, RSTM_HEADER},
+    container::AudioContainer,
+    dsp::{is_plausible_dsp, read_dsp_bytes},
+    genh::{read_genh_bytes, GENH_HEADER},
+    hps::{read_hps_bytes, HPS_HEADER},
+    idsp::{read_idsp_bytes, DecodeError, IdspDialect},
+    nus3audio::{AUDIINDX, NUS3_HEADER},
+    nus3bank::BANKTOC,
+};
+use alloc::boxed::Box;
+#[cfg(feature = "std")]
+use std::{fs::File, io::Read, path::Path};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Idsp,
+    /// A headerless, single channel `.dsp` file.
+    Dsp,
+    Hps,
+    Brstm,
+    Genh,
+    Nus3Audio,
+    Nus3Bank,
+}
+
+impl Format {
+    /// Whether the format holds several files rather than a single stream. Archives have to
+    /// be read with their own module.
+    pub fn is_archive(self) -> bool {
+        matches!(self, Format::Nus3Audio | Format::Nus3Bank)
+    }
+}
+
+/// Sniffs the format of `bytes` from its magic number. DSP files have none, so they are only
+/// recognized if their header is plausible, after every other format has been ruled out.
+pub fn detect_format(bytes: &[u8]) -> Option<Format> {
+    let nus3_magic = bytes.get(8..16);
+
+    if IdspDialect::detect(bytes).is_some() {
+        Some(Format::Idsp)
+    } else if bytes.starts_with(HPS_HEADER) {
+        Some(Format::Hps)
+    } else if bytes.starts_with(RSTM_HEADER) {
+        Some(Format::Brstm)
+    } else if bytes.starts_with(GENH_HEADER) {
+        Some(Format::Genh)
+    } else if bytes.starts_with(NUS3_HEADER) && nus3_magic == Some(AUDIINDX) {
+        Some(Format::Nus3Audio)
+    } else if bytes.starts_with(NUS3_HEADER) && nus3_magic == Some(BANKTOC) {
+        Some(Format::Nus3Bank)
+    } else if is_plausible_dsp(bytes) {
+        Some(Format::Dsp)
+    } else {
+        None
+    }
+}
+
+#[cfg(feature = "std")]
+pub fn open<P: AsRef<Path>>(file_path: P) -> Result<Box<dyn AudioContainer>, DecodeError> {
+    let mut file = File::open(file_path)?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+
+    read_bytes(&bytes)
+}
+
+/// Reads a single stream in any format [`detect_format`] recognizes. Archives give
+/// [`DecodeError::UnsupportedFormat`].
+pub fn read_bytes(bytes: &[u8]) -> Result<Box<dyn AudioContainer>, DecodeError> {
+    let format = detect_format(bytes).ok_or(DecodeError::UnsupportedFormat)?;
+
+    Ok(match format {
+        Format::Idsp => Box::new(read_idsp_bytes(bytes)?),
+        Format::Dsp => Box::new(read_dsp_bytes(bytes)?),
+        Format::Hps => Box::new(read_hps_bytes(bytes)?),
+        Format::Brstm => Box::new(read_brstm_bytes(bytes)?),
+        Format::Genh => Box::new(read_genh_bytes(bytes)?),
+        Format::Nus3Audio | Format::Nus3Bank => return Err(DecodeError::UnsupportedFormat),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        brstm::write_brstm_bytes,
+        dsp::write_dsp_bytes,
+        genh::write_genh_bytes,
+        hps::write_hps_bytes,
+        idsp::write_idsp_bytes,
+        nus3audio::{write_nus3audio_bytes, Nus3Audio, Nus3AudioEntry},
+    };
+
+    #[test]
+    fn test_detect_format() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+        let archive = Nus3Audio {
+            entries: vec![Nus3AudioEntry { id: 0, name: "13".into(), data: idsp_bytes.to_vec() }],
+        };
+
+        let files = [
+            (idsp_bytes.to_vec(), Format::Idsp),
+            (write_dsp_bytes(&idsp_file.channels[0]), Format::Dsp),
+            (write_hps_bytes(&idsp_file).unwrap(), Format::Hps),
+            (write_brstm_bytes(&idsp_file).unwrap(), Format::Brstm),
+            (write_genh_bytes(&idsp_file).unwrap(), Format::Genh),
+            (write_nus3audio_bytes(&archive), Format::Nus3Audio),
+        ];
+
+        for (bytes, format) in &files {
+            assert_eq!(detect_format(bytes), Some(*format));
+
+            if format.is_archive() {
+                assert!(matches!(read_bytes(bytes), Err(DecodeError::UnsupportedFormat)));
+            } else {
+                let pcm = read_bytes(bytes).unwrap().decode_channel(0).unwrap();
+                assert_eq!(pcm, idsp_file.channels[0].decode());
+            }
+        }
+
+        let mut little_endian = idsp_file.clone();
+        little_endian.dialect = IdspDialect::LittleEndian;
+        let little_endian = write_idsp_bytes(&little_endian).unwrap();
+        assert_eq!(detect_format(&little_endian), Some(Format::Idsp));
+        assert_eq!(read_bytes(&little_endian).unwrap().sample_count(), 47035);
+    }
+
+    #[test]
+    fn test_detect_garbage() {
+        assert_eq!(detect_format(&[]), None);
+        assert_eq!(detect_format(b"IDSP"), None);
+        assert_eq!(detect_format(&[0; 0x1000]), None);
+        assert!(matches!(read_bytes(b"RIFF"), Err(DecodeError::UnsupportedFormat)));
+    }
+}
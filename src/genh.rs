@@ -0,0 +1,469 @@
+//! GENH headers and headerless GC-ADPCM data.
+//!
+//! GENH is a generic little-endian header that gets prepended to raw audio ripped from game
+//! archives, describing where the audio and the coefficients live:
+//!
+//! | Offset | Field                                                        |
+//! |--------|--------------------------------------------------------------|
+//! | 0x00   | `GENH`                                                       |
+//! | 0x04   | Channel count                                                |
+//! | 0x08   | Interleave size                                              |
+//! | 0x0C   | Sample rate                                                  |
+//! | 0x10   | Loop start, or -1 if the stream doesn't loop                 |
+//! | 0x14   | Loop end                                                     |
+//! | 0x18   | Codec, 12 for GC-ADPCM                                       |
+//! | 0x1C   | Audio data offset                                            |
+//! | 0x20   | Header size                                                  |
+//! | 0x24   | Coefficient offset of channel 0                              |
+//! | 0x28   | Coefficient offset of channel 1, which sets the stride        |
+//! | 0x2C   | Interleave type: 0 for interleaved, 2 for one channel after another |
+//! | 0x30   | Coefficient type: bit 0 for split tables, bit 1 for little endian |
+//! | 0x34   | Split coefficient offset of channel 0                        |
+//! | 0x38   | Split coefficient offset of channel 1                        |
+//! | 0x40   | Sample count, only if the header size is at least 0x54       |
+//!
+//! All offsets are from the start of the file, header included. Without the sample count of
+//! the extended header, it is derived from the length of the audio data.
+
+use crate::{
+    coefficients::Coefficients,
+    idsp::{
+        deinterleave, interleave, BlockPadding, Channel, DecodeError, EncodeError, IdspContainer,
+        IdspDialect,
+    },
+    math::{byte_count_to_sample_count, get_next_multiple, sample_count_to_byte_count},
+};
+use alloc::{vec, vec::Vec};
+use bytes::{Buf, BufMut, BytesMut};
+#[cfg(feature = "std")]
+use std::{fs::File, io::Read, path::Path};
+
+pub(crate) const GENH_HEADER: &[u8] = b"GENH";
+const GENH_HEADER_SIZE: usize = 0x40;
+const GENH_EXTENDED_HEADER_SIZE: usize = 0x54;
+const MAX_CHANNELS: usize = 16;
+const CODEC_GC_ADPCM: i32 = 12;
+const INTERLEAVE_TYPE_NORMAL: i32 = 0;
+const INTERLEAVE_TYPE_NONE: i32 = 2;
+const COEFFICIENT_TYPE_SPLIT: i32 = 1;
+const COEFFICIENT_TYPE_LITTLE_ENDIAN: i32 = 2;
+const COEFFICIENT_TABLE_SIZE: usize = 0x20;
+const AUDIO_ALIGNMENT: usize = 0x20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ByteOrder {
+    BigEndian,
+    LittleEndian,
+}
+
+/// Where the pieces of a headerless GC-ADPCM stream live, for [`read_raw_bytes`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct RawParameters {
+    pub channel_count: usize,
+    /// Bytes of each channel per interleave block, or 0 if each channel's audio is stored
+    /// in full before the next one.
+    pub interleave_size: usize,
+    pub sample_rate: usize,
+    pub audio_offset: usize,
+    /// The number of samples in each channel, or `None` to use all the data after
+    /// `audio_offset`.
+    pub sample_count: Option<usize>,
+    /// The offset of each channel's 16 coefficients.
+    pub coefficient_offsets: Vec<usize>,
+    /// If set, `coefficient_offsets` only hold every coef_1 and these offsets hold every
+    /// coef_2, as 8 values each.
+    pub split_coefficient_offsets: Option<Vec<usize>>,
+    pub coefficient_byte_order: ByteOrder,
+    /// `(start, end)` in samples, with `end` exclusive.
+    pub loop_points: Option<(usize, usize)>,
+}
+
+#[cfg(feature = "std")]
+pub fn read_genh<P: AsRef<Path>>(file_path: P) -> Result<IdspContainer, DecodeError> {
+    let mut file = File::open(file_path)?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+
+    read_genh_bytes(&bytes)
+}
+
+pub fn read_genh_bytes(original_bytes: &[u8]) -> Result<IdspContainer, DecodeError> {
+    read_raw_bytes(original_bytes, &read_genh_parameters(original_bytes)?)
+}
+
+/// Parses a GENH header into the parameters [`read_raw_bytes`] needs.
+pub fn read_genh_parameters(original_bytes: &[u8]) -> Result<RawParameters, DecodeError> {
+    if !original_bytes.starts_with(GENH_HEADER) {
+        return Err(DecodeError::InvalidHeader);
+    }
+
+    let mut bytes = original_bytes
+        .get(GENH_HEADER.len()..GENH_HEADER_SIZE)
+        .ok_or(DecodeError::InvalidHeader)?;
+
+    let channel_count = get_non_negative(&mut bytes)?;
+    let interleave_size = get_non_negative(&mut bytes)?;
+    let sample_rate = get_non_negative(&mut bytes)?;
+    let loop_start = bytes.get_i32_le();
+    let loop_end = bytes.get_i32_le();
+    let codec = bytes.get_i32_le();
+    let audio_offset = get_non_negative(&mut bytes)?;
+    let header_size = get_non_negative(&mut bytes)?;
+    let coefficient_offset = get_non_negative(&mut bytes)?;
+    let second_coefficient_offset = get_non_negative(&mut bytes)?;
+    let interleave_type = bytes.get_i32_le();
+    let coefficient_type = bytes.get_i32_le();
+    let split_coefficient_offset = get_non_negative(&mut bytes)?;
+    let second_split_coefficient_offset = get_non_negative(&mut bytes)?;
+
+    if codec != CODEC_GC_ADPCM || channel_count == 0 || channel_count > MAX_CHANNELS {
+        return Err(DecodeError::InvalidHeader);
+    }
+
+    let sample_count = if header_size >= GENH_EXTENDED_HEADER_SIZE {
+        let mut bytes = original_bytes
+            .get(GENH_HEADER_SIZE..GENH_EXTENDED_HEADER_SIZE)
+            .ok_or(DecodeError::InvalidHeader)?;
+
+        match get_non_negative(&mut bytes)? {
+            0 => None,
+            sample_count => Some(sample_count),
+        }
+    } else {
+        None
+    };
+
+    let interleave_size = match interleave_type {
+        INTERLEAVE_TYPE_NORMAL => interleave_size,
+        INTERLEAVE_TYPE_NONE => 0,
+        _ => return Err(DecodeError::InvalidHeader),
+    };
+
+    // Channels past the second continue at the same stride as the first two.
+    let offsets = |first: usize, second: usize| -> Vec<usize> {
+        let stride = second.wrapping_sub(first);
+        (0..channel_count).map(|i| first.wrapping_add(stride.wrapping_mul(i))).collect()
+    };
+
+    let split_coefficient_offsets = if coefficient_type & COEFFICIENT_TYPE_SPLIT != 0 {
+        Some(offsets(split_coefficient_offset, second_split_coefficient_offset))
+    } else {
+        None
+    };
+
+    let coefficient_byte_order = if coefficient_type & COEFFICIENT_TYPE_LITTLE_ENDIAN != 0 {
+        ByteOrder::LittleEndian
+    } else {
+        ByteOrder::BigEndian
+    };
+
+    let loop_points = if loop_start >= 0 && loop_end > loop_start {
+        Some((loop_start as usize, loop_end as usize))
+    } else {
+        None
+    };
+
+    Ok(RawParameters {
+        channel_count,
+        interleave_size,
+        sample_rate,
+        audio_offset,
+        sample_count,
+        coefficient_offsets: offsets(coefficient_offset, second_coefficient_offset),
+        split_coefficient_offsets,
+        coefficient_byte_order,
+        loop_points,
+    })
+}
+
+fn get_non_negative(bytes: &mut &[u8]) -> Result<usize, DecodeError> {
+    match bytes.get_i32_le() {
+        value if value < 0 => Err(DecodeError::InvalidHeader),
+        value => Ok(value as usize),
+    }
+}
+
+/// Builds a container from GC-ADPCM data whose layout is known from elsewhere.
+pub fn read_raw_bytes(
+    original_bytes: &[u8],
+    parameters: &RawParameters,
+) -> Result<IdspContainer, DecodeError> {
+    let channel_count = parameters.channel_count;
+
+    if channel_count == 0 || parameters.coefficient_offsets.len() < channel_count {
+        return Err(DecodeError::InvalidHeader);
+    }
+
+    let audio =
+        original_bytes.get(parameters.audio_offset..).ok_or(DecodeError::InvalidAudioLength)?;
+    let available_len = audio.len() / channel_count;
+
+    let (sample_count, channel_len) = match parameters.sample_count {
+        // Without interleaving, each channel takes up an equal share of the data.
+        Some(sample_count) if parameters.interleave_size == 0 => (sample_count, available_len),
+        Some(sample_count) => {
+            let byte_count = sample_count_to_byte_count(sample_count);
+            (
+                sample_count,
+                get_next_multiple(byte_count, parameters.interleave_size).min(available_len),
+            )
+        },
+        None => (byte_count_to_sample_count(available_len), available_len),
+    };
+
+    let byte_count = sample_count_to_byte_count(sample_count);
+    if byte_count > channel_len || byte_count == 0 {
+        return Err(DecodeError::InvalidAudioLength);
+    }
+
+    let interleave_size =
+        if parameters.interleave_size == 0 { channel_len } else { parameters.interleave_size };
+
+    let audio_data = deinterleave(
+        audio,
+        channel_len * channel_count,
+        interleave_size,
+        channel_count,
+        Some(byte_count),
+    )?;
+
+    if let Some((loop_start, loop_end)) = parameters.loop_points {
+        if loop_start >= loop_end || loop_end > sample_count {
+            return Err(DecodeError::InvalidHeader);
+        }
+    }
+
+    let mut channels = vec![];
+    for (i, audio) in audio_data.into_iter().enumerate() {
+        let coefficients = read_coefficients(original_bytes, parameters, i)?;
+
+        channels.push(Channel::from_adpcm(
+            audio,
+            coefficients,
+            sample_count,
+            parameters.sample_rate,
+            parameters.loop_points,
+        ));
+    }
+
+    let (loop_start, loop_end) = parameters.loop_points.unwrap_or((0, 0));
+
+    Ok(IdspContainer {
+        looping: parameters.loop_points.is_some(),
+        channel_count,
+        sample_rate: parameters.sample_rate,
+        loop_start,
+        loop_end,
+        sample_count,
+        interleave_size: parameters.interleave_size,
+        padding: BlockPadding::FullBlock,
+        header_size: crate::idsp::STREAM_INFO_SIZE,
+        channel_info_size: crate::idsp::CHANNEL_INFO_SIZE,
+        dialect: IdspDialect::BigEndian,
+        channels,
+    })
+}
+
+fn read_coefficients(
+    bytes: &[u8],
+    parameters: &RawParameters,
+    channel: usize,
+) -> Result<Coefficients, DecodeError> {
+    let read = |offset: usize| -> Result<Coefficients, DecodeError> {
+        let bytes = bytes.get(offset..).ok_or(DecodeError::InvalidHeader)?;
+        let coefficients = match parameters.coefficient_byte_order {
+            ByteOrder::BigEndian => Coefficients::from_be_bytes(bytes),
+            ByteOrder::LittleEndian => Coefficients::from_le_bytes(bytes),
+        };
+
+        coefficients.ok_or(DecodeError::InvalidHeader)
+    };
+
+    let offset = parameters.coefficient_offsets[channel];
+
+    match &parameters.split_coefficient_offsets {
+        Some(split_offsets) => {
+            let split_offset = *split_offsets.get(channel).ok_or(DecodeError::InvalidHeader)?;
+
+            // Each split table is only 8 values long, so read them as half of a full table.
+            let first = read_half(bytes, offset, parameters.coefficient_byte_order)?;
+            let second = read_half(bytes, split_offset, parameters.coefficient_byte_order)?;
+            let mut coefs = [0i16; 16];
+
+            for i in 0..8 {
+                coefs[i * 2] = first[i];
+                coefs[i * 2 + 1] = second[i];
+            }
+
+            Ok(Coefficients::new(coefs))
+        },
+        None => read(offset),
+    }
+}
+
+fn read_half(bytes: &[u8], offset: usize, byte_order: ByteOrder) -> Result<[i16; 8], DecodeError> {
+    let end = offset.checked_add(COEFFICIENT_TABLE_SIZE / 2).ok_or(DecodeError::InvalidHeader)?;
+    let mut bytes = bytes.get(offset..end).ok_or(DecodeError::InvalidHeader)?;
+    let mut values = [0i16; 8];
+
+    for value in &mut values {
+        *value = match byte_order {
+            ByteOrder::BigEndian => bytes.get_i16(),
+            ByteOrder::LittleEndian => bytes.get_i16_le(),
+        };
+    }
+
+    Ok(values)
+}
+
+/// Writes `container` as a GENH file, with big-endian coefficient tables after the header.
+pub fn write_genh_bytes(container: &IdspContainer) -> Result<Vec<u8>, EncodeError> {
+    let channel_count = container.channels.len();
+
+    if channel_count == 0 || channel_count != container.channel_count {
+        return Err(EncodeError::InvalidChannelCount);
+    }
+
+    let audio_offset = get_next_multiple(
+        GENH_EXTENDED_HEADER_SIZE + channel_count * COEFFICIENT_TABLE_SIZE,
+        AUDIO_ALIGNMENT,
+    );
+    let coefficient_offset =
+        |i: usize| (GENH_EXTENDED_HEADER_SIZE + i * COEFFICIENT_TABLE_SIZE) as i32;
+    let (loop_start, loop_end) = if container.looping {
+        (container.loop_start as i32, container.loop_end as i32)
+    } else {
+        (-1, 0)
+    };
+
+    let interleave_type =
+        if container.interleave_size == 0 { INTERLEAVE_TYPE_NONE } else { INTERLEAVE_TYPE_NORMAL };
+
+    let mut bytes = BytesMut::new();
+    bytes.extend_from_slice(GENH_HEADER);
+    bytes.put_i32_le(channel_count as i32);
+    bytes.put_i32_le(container.interleave_size as i32);
+    bytes.put_i32_le(container.sample_rate as i32);
+    bytes.put_i32_le(loop_start);
+    bytes.put_i32_le(loop_end);
+    bytes.put_i32_le(CODEC_GC_ADPCM);
+    bytes.put_i32_le(audio_offset as i32);
+    bytes.put_i32_le(GENH_EXTENDED_HEADER_SIZE as i32);
+    bytes.put_i32_le(coefficient_offset(0));
+    bytes.put_i32_le(coefficient_offset(1));
+    bytes.put_i32_le(interleave_type);
+    bytes.put_i32_le(0);
+    bytes.put_i32_le(0);
+    bytes.put_i32_le(0);
+    bytes.resize(GENH_HEADER_SIZE, 0);
+    bytes.put_i32_le(container.sample_count as i32);
+    bytes.resize(GENH_EXTENDED_HEADER_SIZE, 0);
+
+    for channel in &container.channels {
+        bytes.extend_from_slice(&channel.metadata.coefficients.to_be_bytes());
+    }
+
+    bytes.resize(audio_offset, 0);
+
+    bytes.extend_from_slice(&interleave(
+        &container.channels,
+        container.interleave_size,
+        Some(container.audio_data_len()),
+    ));
+
+    Ok(bytes.to_vec())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::idsp::read_idsp_bytes;
+
+    #[test]
+    fn test_genh_roundtrip() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let mut idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+        idsp_file.looping = true;
+        idsp_file.loop_start = 1000;
+        idsp_file.loop_end = 40000;
+
+        let genh_bytes = write_genh_bytes(&idsp_file).unwrap();
+        let genh_file = read_genh_bytes(&genh_bytes).unwrap();
+
+        assert_eq!(genh_file.sample_rate, idsp_file.sample_rate);
+        assert_eq!((genh_file.loop_start, genh_file.loop_end), (1000, 40000));
+        assert_eq!(genh_file.sample_count, idsp_file.sample_count);
+
+        let (original, converted) = (&idsp_file.channels[0], &genh_file.channels[0]);
+        assert_eq!(converted.metadata.coefficients, original.metadata.coefficients);
+        assert_eq!(converted.audio[..original.audio.len()], original.audio[..]);
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let genh_bytes = write_genh_bytes(&read_idsp_bytes(idsp_bytes).unwrap()).unwrap();
+
+        let with_field = |offset: usize, value: i32| {
+            let mut bytes = genh_bytes.clone();
+            bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            read_genh_bytes(&bytes)
+        };
+
+        // Channel count, then channel 1's coefficient offset.
+        assert!(matches!(with_field(0x04, -1), Err(DecodeError::InvalidHeader)));
+        assert!(matches!(with_field(0x04, 1000), Err(DecodeError::InvalidHeader)));
+        assert!(matches!(with_field(0x28, -8), Err(DecodeError::InvalidHeader)));
+
+        // Split tables at 8 and 0, so channel 2's wraps around to -8.
+        let mut bytes = genh_bytes.clone();
+        bytes[0x04..0x08].copy_from_slice(&3i32.to_le_bytes());
+        bytes[0x40..0x44].copy_from_slice(&0i32.to_le_bytes());
+        bytes[0x30..0x34].copy_from_slice(&COEFFICIENT_TYPE_SPLIT.to_le_bytes());
+        bytes[0x34..0x38].copy_from_slice(&8i32.to_le_bytes());
+        assert!(matches!(read_genh_bytes(&bytes), Err(DecodeError::InvalidHeader)));
+    }
+
+    #[test]
+    fn test_raw_stereo() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+        let channel = &idsp_file.channels[0];
+        let coefficients = channel.metadata.coefficients;
+
+        // Little-endian split tables, followed by two identical channels interleaved by 0x100.
+        let mut raw = vec![];
+        for i in 0..8 {
+            raw.extend_from_slice(&coefficients.predictor(i).0.to_le_bytes());
+        }
+        for i in 0..8 {
+            raw.extend_from_slice(&coefficients.predictor(i).1.to_le_bytes());
+        }
+        for block in channel.audio.chunks(0x100) {
+            raw.extend_from_slice(block);
+            raw.extend_from_slice(block);
+        }
+
+        let parameters = RawParameters {
+            channel_count: 2,
+            interleave_size: 0x100,
+            sample_rate: 32000,
+            audio_offset: 0x20,
+            sample_count: Some(idsp_file.sample_count),
+            coefficient_offsets: vec![0, 0],
+            split_coefficient_offsets: Some(vec![0x10, 0x10]),
+            coefficient_byte_order: ByteOrder::LittleEndian,
+            loop_points: Some((7, idsp_file.sample_count)),
+        };
+
+        let container = read_raw_bytes(&raw, &parameters).unwrap();
+
+        assert_eq!(container.channel_count, 2);
+        assert_eq!(container.sample_count, idsp_file.sample_count);
+        for converted in &container.channels {
+            assert_eq!(converted.audio, channel.audio);
+            assert_eq!(converted.metadata.coefficients, coefficients);
+            assert_eq!(converted.metadata.start_address, 9);
+        }
+    }
+}
@@ -0,0 +1,413 @@
+//! HAL's `.hps` streams, as used by Super Smash Bros. Melee.
+//!
+//! An HPS file starts with a 0x80 byte header: the magic, the sample rate, the channel count
+//! and two 0x38 byte DSP channel headers (block size, addresses, coefficients, gain and the
+//! start context). The audio follows as a linked list of blocks. Each block has a 0x20 byte
+//! header holding the size of its data, the address of its last nibble, the offset of the
+//! next block and a context per channel, followed by the data of each channel in turn. The
+//! last block links back to the loop block, or to `0xFFFFFFFF` if the stream doesn't loop.
+
+use crate::{
+    coefficients::Coefficients,
+    decode::decode_gc_adpcm_into,
+    idsp::{
+        BlockPadding, Channel, ChannelMetadata, DecodeError, EncodeError, GcAdpcmContext,
+        IdspContainer, IdspDialect, STREAM_INFO_SIZE,
+    },
+    math::{
+        get_next_multiple, nibble_address_to_sample, sample_count_to_byte_count,
+        sample_to_nibble_address,
+    },
+    BYTES_PER_FRAME, SAMPLES_PER_FRAME,
+};
+use alloc::{vec, vec::Vec};
+use bytes::{Buf, BufMut, BytesMut};
+#[cfg(feature = "std")]
+use std::{fs::File, io::Read, path::Path};
+
+pub(crate) const HPS_HEADER: &[u8] = b" HALPST\0";
+const HEADER_SIZE: usize = 0x80;
+const CHANNEL_INFO_OFFSET: usize = 0x10;
+const CHANNEL_INFO_SIZE: usize = 0x38;
+const BLOCK_HEADER_SIZE: usize = 0x20;
+const BLOCK_ALIGNMENT: usize = 0x20;
+const MAX_CHANNELS: usize = 2;
+const END_OF_STREAM: u32 = 0xFFFF_FFFF;
+
+/// The number of bytes of each channel in a full block, as written by Melee.
+pub const DEFAULT_BLOCK_SIZE: usize = 0x10000;
+
+#[cfg(feature = "std")]
+pub fn read_hps<P: AsRef<Path>>(file_path: P) -> Result<IdspContainer, DecodeError> {
+    let mut file = File::open(file_path)?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+
+    read_hps_bytes(&bytes)
+}
+
+pub fn read_hps_bytes(original_bytes: &[u8]) -> Result<IdspContainer, DecodeError> {
+    if !original_bytes.starts_with(HPS_HEADER) {
+        return Err(DecodeError::InvalidHeader);
+    }
+
+    let mut bytes =
+        original_bytes.get(HPS_HEADER.len()..HEADER_SIZE).ok_or(DecodeError::InvalidHeader)?;
+
+    let sample_rate = bytes.get_u32() as usize;
+    let channel_count = bytes.get_u32() as usize;
+
+    if channel_count == 0 || channel_count > MAX_CHANNELS {
+        return Err(DecodeError::InvalidHeader);
+    }
+
+    let mut metadatas = vec![];
+    for i in 0..channel_count {
+        let mut bytes = &original_bytes[CHANNEL_INFO_OFFSET + i * CHANNEL_INFO_SIZE..];
+
+        bytes.advance(8);
+        let end_address = bytes.get_u32() as usize;
+        bytes.advance(4);
+        let coefficients = Coefficients::from_be_bytes(bytes).ok_or(DecodeError::InvalidHeader)?;
+        bytes.advance(32);
+        let gain = bytes.get_i16();
+        let start_context = GcAdpcmContext::read_from_buf(&mut bytes);
+
+        let sample_count = nibble_address_to_sample(end_address) + 1;
+        let metadata = ChannelMetadata {
+            gain,
+            start_context,
+            ..ChannelMetadata::new(sample_count, sample_rate, coefficients, None)
+        };
+
+        metadatas.push(metadata);
+    }
+
+    let sample_count = metadatas[0].sample_count;
+    let mut audio_data = vec![Vec::new(); channel_count];
+    let mut blocks: Vec<(usize, usize, Vec<GcAdpcmContext>)> = vec![];
+    let mut loop_block = None;
+    let mut block_offset = HEADER_SIZE;
+    let mut interleave_size = 0;
+
+    loop {
+        let mut bytes = original_bytes
+            .get(block_offset..block_offset + BLOCK_HEADER_SIZE)
+            .ok_or(DecodeError::InvalidAudioLength)?;
+
+        let data_size = bytes.get_u32() as usize;
+        let end_address = bytes.get_u32() as usize;
+        let next_offset = bytes.get_u32();
+        let contexts: Vec<_> = (0..channel_count)
+            .map(|_| {
+                let context = GcAdpcmContext::read_from_buf(&mut bytes);
+                bytes.advance(2);
+                context
+            })
+            .collect();
+
+        let channel_size = data_size / channel_count;
+        let used_size = end_address / 2 + 1;
+        let data_offset = block_offset + BLOCK_HEADER_SIZE;
+        let data = original_bytes
+            .get(data_offset..data_offset + data_size)
+            .ok_or(DecodeError::InvalidAudioLength)?;
+
+        // Every block but the last holds whole frames, so blocks start on a frame boundary.
+        if used_size > channel_size || audio_data[0].len() % BYTES_PER_FRAME != 0 {
+            return Err(DecodeError::InvalidAudioLength);
+        }
+
+        let first_sample = audio_data[0].len() / BYTES_PER_FRAME * SAMPLES_PER_FRAME;
+        interleave_size = interleave_size.max(channel_size);
+        blocks.push((block_offset, first_sample, contexts));
+
+        for (audio, chunk) in audio_data.iter_mut().zip(data.chunks(channel_size)) {
+            audio.extend_from_slice(&chunk[..used_size]);
+        }
+
+        if next_offset == END_OF_STREAM {
+            break;
+        }
+
+        if let Some(index) = blocks.iter().position(|block| block.0 == next_offset as usize) {
+            loop_block = Some(index);
+            break;
+        }
+
+        block_offset = next_offset as usize;
+    }
+
+    let byte_count = sample_count_to_byte_count(sample_count);
+    if audio_data[0].len() < byte_count {
+        return Err(DecodeError::InvalidAudioLength);
+    }
+
+    let (looping, loop_start, loop_end) = match loop_block {
+        Some(index) => (true, blocks[index].1, sample_count),
+        None => (false, 0, 0),
+    };
+
+    let channels = metadatas
+        .into_iter()
+        .zip(audio_data)
+        .enumerate()
+        .map(|(i, (metadata, mut audio))| {
+            audio.truncate(byte_count);
+
+            let metadata = match loop_block {
+                Some(index) => ChannelMetadata {
+                    gain: metadata.gain,
+                    start_context: metadata.start_context,
+                    loop_context: blocks[index].2[i].clone(),
+                    ..ChannelMetadata::new(
+                        sample_count,
+                        sample_rate,
+                        metadata.coefficients,
+                        Some((loop_start, loop_end)),
+                    )
+                },
+                None => metadata,
+            };
+
+            Channel { metadata, audio }
+        })
+        .collect();
+
+    Ok(IdspContainer {
+        looping,
+        channel_count,
+        sample_rate,
+        loop_start,
+        loop_end,
+        sample_count,
+        interleave_size,
+        padding: BlockPadding::FullBlock,
+        header_size: STREAM_INFO_SIZE,
+        channel_info_size: crate::idsp::CHANNEL_INFO_SIZE,
+        dialect: IdspDialect::BigEndian,
+        channels,
+    })
+}
+
+pub fn write_hps_bytes(container: &IdspContainer) -> Result<Vec<u8>, EncodeError> {
+    write_hps_bytes_with_block_size(container, DEFAULT_BLOCK_SIZE)
+}
+
+/// Writes `container` as HPS, splitting each channel into blocks of `block_size` bytes.
+///
+/// HPS streams always loop to their end and can only loop back to the start of a block, so a
+/// looping container is cut off at `loop_end` and its `loop_start` must be frame aligned.
+pub fn write_hps_bytes_with_block_size(
+    container: &IdspContainer,
+    block_size: usize,
+) -> Result<Vec<u8>, EncodeError> {
+    let channels = &container.channels;
+    let channel_count = channels.len();
+
+    if channel_count == 0
+        || channel_count > MAX_CHANNELS
+        || channel_count != container.channel_count
+    {
+        return Err(EncodeError::InvalidChannelCount);
+    }
+
+    if block_size == 0 || !block_size.is_multiple_of(BLOCK_ALIGNMENT) {
+        return Err(EncodeError::InvalidBlockSize);
+    }
+
+    let looping = container.looping;
+    let sample_count = if looping { container.loop_end } else { container.sample_count };
+
+    if looping
+        && (!container.loop_start.is_multiple_of(SAMPLES_PER_FRAME)
+            || container.loop_start >= container.loop_end
+            || container.loop_end > container.sample_count)
+    {
+        return Err(EncodeError::InvalidLoopPoints);
+    }
+
+    let byte_count = sample_count_to_byte_count(sample_count);
+    if sample_count == 0 || channels.iter().any(|channel| channel.audio.len() < byte_count) {
+        return Err(EncodeError::InvalidAudioLength);
+    }
+
+    // Each block covers a byte range of every channel, and one always starts at the loop.
+    let loop_byte =
+        if looping { container.loop_start / SAMPLES_PER_FRAME * BYTES_PER_FRAME } else { 0 };
+    let mut ranges = vec![];
+    for &(start, end) in &[(0, loop_byte), (loop_byte, byte_count)] {
+        for block_start in (start..end).step_by(block_size) {
+            ranges.push((block_start, (block_start + block_size).min(end)));
+        }
+    }
+
+    let pcm: Vec<Vec<i16>> = channels
+        .iter()
+        .map(|channel| {
+            let mut pcm = vec![0; sample_count];
+            let mut context = channel.metadata.start_context.clone();
+            decode_gc_adpcm_into(
+                &channel.audio[..byte_count],
+                &channel.metadata.coefficients,
+                &mut context,
+                &mut pcm,
+            );
+            pcm
+        })
+        .collect();
+
+    let context_at = |i: usize, sample: usize| {
+        let channel = &channels[i];
+        let mut context = GcAdpcmContext::at_sample(&channel.audio, &pcm[i], sample);
+
+        if sample == 0 {
+            context.hist_1 = channel.metadata.start_context.hist_1;
+            context.hist_2 = channel.metadata.start_context.hist_2;
+        }
+
+        context
+    };
+
+    let largest_block = ranges
+        .iter()
+        .map(|(start, end)| get_next_multiple(end - start, BLOCK_ALIGNMENT) * channel_count)
+        .max()
+        .unwrap_or(0);
+
+    let mut bytes = BytesMut::new();
+    bytes.extend_from_slice(HPS_HEADER);
+    bytes.put_u32(container.sample_rate as u32);
+    bytes.put_u32(channel_count as u32);
+
+    for (i, channel) in channels.iter().enumerate() {
+        bytes.put_u32(largest_block as u32);
+        bytes.put_u32(sample_to_nibble_address(0) as u32);
+        bytes.put_u32(sample_to_nibble_address(sample_count - 1) as u32);
+        bytes.put_u32(sample_to_nibble_address(0) as u32);
+        bytes.extend_from_slice(&channel.metadata.coefficients.to_be_bytes());
+        bytes.put_i16(channel.metadata.gain);
+        context_at(i, 0).write_to_buf(&mut bytes);
+    }
+
+    bytes.resize(HEADER_SIZE, 0);
+
+    let loop_block_offset = HEADER_SIZE
+        + ranges
+            .iter()
+            .take_while(|(start, _)| *start < loop_byte)
+            .map(|(start, end)| {
+                BLOCK_HEADER_SIZE + get_next_multiple(end - start, BLOCK_ALIGNMENT) * channel_count
+            })
+            .sum::<usize>();
+
+    for (index, &(start, end)) in ranges.iter().enumerate() {
+        let channel_size = get_next_multiple(end - start, BLOCK_ALIGNMENT);
+        let block_offset = bytes.len();
+        let first_sample = start / BYTES_PER_FRAME * SAMPLES_PER_FRAME;
+        let last_sample = if end == byte_count {
+            sample_count
+        } else {
+            end / BYTES_PER_FRAME * SAMPLES_PER_FRAME
+        };
+
+        let next_offset = if index + 1 < ranges.len() {
+            (block_offset + BLOCK_HEADER_SIZE + channel_size * channel_count) as u32
+        } else if looping {
+            loop_block_offset as u32
+        } else {
+            END_OF_STREAM
+        };
+
+        bytes.put_u32((channel_size * channel_count) as u32);
+        bytes.put_u32(sample_to_nibble_address(last_sample - first_sample - 1) as u32);
+        bytes.put_u32(next_offset);
+
+        for i in 0..MAX_CHANNELS {
+            if i < channel_count {
+                context_at(i, first_sample).write_to_buf(&mut bytes);
+            } else {
+                GcAdpcmContext::default().write_to_buf(&mut bytes);
+            }
+            bytes.put_u16(0);
+        }
+
+        bytes.resize(block_offset + BLOCK_HEADER_SIZE, 0);
+
+        for channel in channels {
+            let data_offset = bytes.len();
+            bytes.extend_from_slice(&channel.audio[start..end]);
+            bytes.resize(data_offset + channel_size, 0);
+        }
+    }
+
+    Ok(bytes.to_vec())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::idsp::read_idsp_bytes;
+
+    #[test]
+    fn test_hps_roundtrip() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+
+        let hps_bytes = write_hps_bytes(&idsp_file).unwrap();
+        let hps_file = read_hps_bytes(&hps_bytes).unwrap();
+
+        assert!(!hps_file.looping);
+        assert_eq!(hps_file.sample_rate, idsp_file.sample_rate);
+        assert_eq!(hps_file.sample_count, idsp_file.sample_count);
+
+        let (original, converted) = (&idsp_file.channels[0], &hps_file.channels[0]);
+        assert_eq!(converted.audio, original.audio);
+        assert_eq!(converted.metadata.coefficients, original.metadata.coefficients);
+        assert_eq!(converted.metadata.end_address, original.metadata.end_address);
+        assert_eq!(converted.metadata.nibble_count, original.metadata.nibble_count);
+    }
+
+    #[test]
+    fn test_hps_looping_blocks() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let mut idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+        idsp_file.looping = true;
+        idsp_file.loop_start = SAMPLES_PER_FRAME * 500;
+        idsp_file.loop_end = idsp_file.sample_count - 1000;
+
+        let hps_bytes = write_hps_bytes_with_block_size(&idsp_file, 0x800).unwrap();
+        let hps_file = read_hps_bytes(&hps_bytes).unwrap();
+
+        assert!(hps_file.looping);
+        assert_eq!(hps_file.loop_start, idsp_file.loop_start);
+        assert_eq!(hps_file.sample_count, idsp_file.loop_end);
+        assert_eq!(hps_file.loop_end, idsp_file.loop_end);
+
+        let channel = &hps_file.channels[0];
+        let original = &idsp_file.channels[0];
+        let pcm = original.decode();
+
+        assert_eq!(channel.audio[..], original.audio[..channel.audio.len()]);
+        assert_eq!(
+            channel.metadata.loop_context,
+            GcAdpcmContext::at_sample(&original.audio, &pcm, idsp_file.loop_start)
+        );
+
+        assert_eq!(channel.decode(), pcm[..hps_file.sample_count]);
+    }
+
+    #[test]
+    fn test_hps_rejects_unaligned_loop() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let mut idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+        idsp_file.looping = true;
+        idsp_file.loop_start = 1000;
+        idsp_file.loop_end = idsp_file.sample_count;
+
+        match write_hps_bytes(&idsp_file) {
+            Err(EncodeError::InvalidLoopPoints) => {},
+            other => panic!("expected InvalidLoopPoints, got {:?}", other),
+        }
+    }
+}
@@ -1,28 +1,78 @@
-use crate::math::{get_next_multiple, sample_count_to_byte_count, DivideByRoundUp};
-use bytes::{Buf, BufMut, Bytes, BytesMut};
-use std::{
-    fs::File,
-    io::{Cursor, Read},
-    path::Path,
+use crate::{
+    coefficients::Coefficients,
+    decode::{decode_gc_adpcm_from, decode_gc_adpcm_into},
+    encode::encode_gc_adpcm_into,
+    math::{
+        get_next_multiple, sample_count_to_byte_count, sample_count_to_nibble_count,
+        sample_to_nibble_address, DivideByRoundUp,
+    },
+    BYTES_PER_FRAME, SAMPLES_PER_FRAME,
 };
+use alloc::{vec, vec::Vec};
+use bytes::{Buf, BufMut, BytesMut};
+use core::fmt;
+#[cfg(feature = "std")]
+use std::{fs::File, io::Read, path::Path};
 
 const IDSP_HEADER: &[u8] = b"IDSP";
-const STREAM_INFO_SIZE: usize = 0x40;
-const CHANNEL_INFO_SIZE: usize = 0x60;
+pub(crate) const STREAM_INFO_SIZE: usize = 0x40;
+pub(crate) const CHANNEL_INFO_SIZE: usize = 0x60;
+/// The stream info fields end at 0x30, so channel info can't start before it.
+const MIN_STREAM_INFO_SIZE: usize = 0x30;
+pub(crate) const DSP_CHANNEL_HEADER_SIZE: usize = 0x4A;
+/// More than any known IDSP file has, to reject implausible headers.
+const MAX_CHANNELS: usize = 16;
 
 #[derive(Debug)]
 pub enum DecodeError {
+    #[cfg(feature = "std")]
     Io(std::io::Error),
     InvalidHeader,
     InvalidAudioLength,
+    /// The data isn't in a format [`crate::format::read_bytes`] can load as a single stream.
+    UnsupportedFormat,
 }
 
+/// Why a container couldn't be written, returned by [`write_idsp_bytes`] and the other writers.
+#[derive(Debug)]
+pub enum EncodeError {
+    InvalidChannelCount,
+    InvalidLoopPoints,
+    InvalidBlockSize,
+    InvalidAudioLength,
+    /// Channels being combined differ in sample rate, length or loop points.
+    MismatchedChannels,
+    /// The stream or channel info is too small to hold its fields.
+    InvalidHeaderSize,
+    /// The sample rate is zero or doesn't fit in the format's header.
+    InvalidSampleRate,
+    /// An index, such as a tone in a bank, is out of range.
+    InvalidIndex,
+}
+
+/// An inconsistency found by [`IdspContainer::validate`]. `channel` is an index into
+/// `IdspContainer::channels`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Issue {
+    ChannelCountMismatch { channel_count: usize, channels: usize },
+    SampleCountMismatch { channel: usize, expected: usize, actual: usize },
+    NibbleCountMismatch { channel: usize, expected: usize, actual: usize },
+    EndAddressMismatch { channel: usize, expected: usize, actual: usize },
+    AudioTooShort { channel: usize, expected: usize, actual: usize },
+    LoopOutOfBounds { loop_start: usize, loop_end: usize, sample_count: usize },
+    LoopContextMismatch { channel: usize, expected: i16, actual: i16 },
+    SampleRateMismatch { channel: usize, expected: usize, actual: usize },
+    MisalignedInterleave { interleave_size: usize },
+}
+
+#[cfg(feature = "std")]
 impl From<std::io::Error> for DecodeError {
     fn from(err: std::io::Error) -> Self {
         DecodeError::Io(err)
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Clone, Debug, PartialEq)]
 pub struct IdspContainer {
     pub looping: bool,
@@ -31,20 +81,391 @@ pub struct IdspContainer {
     pub loop_start: usize,
     pub loop_end: usize,
     pub sample_count: usize,
+    /// Bytes of each channel per block of audio data, or 0 to store each channel in full
+    /// before the next one.
     pub interleave_size: usize,
+    pub padding: BlockPadding,
+    /// Bytes of stream info before the first channel's info.
     pub header_size: usize,
+    /// Bytes of info per channel, of which the first 0x4A hold a DSP header.
+    pub channel_info_size: usize,
+    pub dialect: IdspDialect,
     pub channels: Vec<Channel>,
 }
 
+/// The byte order of an IDSP header, which differs between the games using the format.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IdspDialect {
+    #[default]
+    BigEndian,
+    LittleEndian,
+}
+
+impl IdspDialect {
+    /// Guesses the dialect of an IDSP file by checking which byte order gives header fields
+    /// that make sense together.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if !bytes.starts_with(IDSP_HEADER) || bytes.len() < STREAM_INFO_SIZE {
+            return None;
+        }
+
+        [IdspDialect::BigEndian, IdspDialect::LittleEndian].iter().copied().find(|dialect| {
+            let mut header = &bytes[8..];
+            let channel_count = dialect.get_i32(&mut header) as usize;
+            header.advance(0x14);
+            let header_size = dialect.get_i32(&mut header) as usize;
+            let channel_info_size = dialect.get_i32(&mut header) as usize;
+            let audio_data_offset = dialect.get_i32(&mut header) as usize;
+
+            // The channel info has to at least hold the fields this crate reads.
+            (1..=MAX_CHANNELS).contains(&channel_count)
+                && (MIN_STREAM_INFO_SIZE..=STREAM_INFO_SIZE * 4).contains(&header_size)
+                && (DSP_CHANNEL_HEADER_SIZE..=CHANNEL_INFO_SIZE * 4).contains(&channel_info_size)
+                && header_size + channel_count * channel_info_size <= audio_data_offset
+                && audio_data_offset <= bytes.len()
+        })
+    }
+
+    fn get_i32(self, buf: &mut &[u8]) -> i32 {
+        match self {
+            IdspDialect::BigEndian => buf.get_i32(),
+            IdspDialect::LittleEndian => buf.get_i32_le(),
+        }
+    }
+
+    fn get_i16(self, buf: &mut &[u8]) -> i16 {
+        match self {
+            IdspDialect::BigEndian => buf.get_i16(),
+            IdspDialect::LittleEndian => buf.get_i16_le(),
+        }
+    }
+
+    fn put_i32(self, buf: &mut BytesMut, value: i32) {
+        match self {
+            IdspDialect::BigEndian => buf.put_i32(value),
+            IdspDialect::LittleEndian => buf.put_i32_le(value),
+        }
+    }
+
+    fn put_i16(self, buf: &mut BytesMut, value: i16) {
+        match self {
+            IdspDialect::BigEndian => buf.put_i16(value),
+            IdspDialect::LittleEndian => buf.put_i16_le(value),
+        }
+    }
+
+    fn read_context(self, buf: &mut &[u8]) -> GcAdpcmContext {
+        GcAdpcmContext {
+            predictor_scale: self.get_i16(buf),
+            hist_1: self.get_i16(buf),
+            hist_2: self.get_i16(buf),
+        }
+    }
+
+    fn write_context(self, context: &GcAdpcmContext, buf: &mut BytesMut) {
+        self.put_i16(buf, context.predictor_scale);
+        self.put_i16(buf, context.hist_1);
+        self.put_i16(buf, context.hist_2);
+    }
+}
+
+/// Interleave size used by the IDSP files this crate was written against.
+pub const DEFAULT_INTERLEAVE_SIZE: usize = 2 * BYTES_PER_FRAME;
+
+/// How far each channel's audio is padded with zeroes when writing it out.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlockPadding {
+    /// Pad every channel to a whole number of interleave blocks.
+    #[default]
+    FullBlock,
+    /// Only pad to a whole frame, leaving the last block of each channel short.
+    Frame,
+}
+
 impl IdspContainer {
+    /// The number of bytes each channel takes up in the audio data, padding included.
     pub fn audio_data_len(&self) -> usize {
-        get_next_multiple(
-            sample_count_to_byte_count(self.sample_count),
-            self.interleave_size,
-        )
+        let byte_count = sample_count_to_byte_count(self.sample_count);
+
+        match self.padding {
+            BlockPadding::FullBlock => get_next_multiple(byte_count, self.interleave_size),
+            BlockPadding::Frame => get_next_multiple(byte_count, BYTES_PER_FRAME),
+        }
+    }
+
+    /// A copy of the headers without the encoded audio, e.g. to store them in a manifest.
+    pub fn without_audio(&self) -> IdspContainer {
+        let mut container = self.clone();
+        for channel in &mut container.channels {
+            channel.audio = Vec::new();
+        }
+
+        container
+    }
+
+    /// Takes the encoded audio of every channel from `other`, e.g. to write edited headers
+    /// back out with the audio they were dumped from.
+    pub fn copy_audio_from(&mut self, other: &IdspContainer) -> Result<(), EncodeError> {
+        if self.channels.len() != other.channels.len() {
+            return Err(EncodeError::MismatchedChannels);
+        }
+
+        for (channel, other) in self.channels.iter_mut().zip(&other.channels) {
+            channel.audio = other.audio.clone();
+        }
+
+        Ok(())
+    }
+
+    /// Changes how the audio data is blocked when written, without touching the encoded
+    /// audio. `interleave_size` must be a multiple of `BYTES_PER_FRAME`, or 0 to store the
+    /// channels one after another.
+    pub fn reblock(
+        &mut self,
+        interleave_size: usize,
+        padding: BlockPadding,
+    ) -> Result<(), EncodeError> {
+        if !interleave_size.is_multiple_of(BYTES_PER_FRAME) {
+            return Err(EncodeError::InvalidBlockSize);
+        }
+
+        self.interleave_size = interleave_size;
+        self.padding = padding;
+
+        Ok(())
+    }
+
+    /// Moves the loop to `loop_points`, `(start, end)` in samples with `end` exclusive, or
+    /// stops looping if it is `None`.
+    ///
+    /// With `align_to_frame`, a loop start inside a frame is pushed forward to the next frame
+    /// boundary and the end along with it. The samples skipped at the start of the loop are
+    /// re-encoded after its end, so only the frames from the old loop end onwards change and
+    /// the stream is cut off at the new loop end.
+    pub fn set_loop_points(
+        &mut self,
+        loop_points: Option<(usize, usize)>,
+        align_to_frame: bool,
+    ) -> Result<(), EncodeError> {
+        let (loop_start, loop_end) = match loop_points {
+            Some(loop_points) => loop_points,
+            None => {
+                for channel in &mut self.channels {
+                    channel.update_loop_points(None);
+                }
+
+                self.looping = false;
+                self.loop_start = 0;
+                self.loop_end = 0;
+
+                return Ok(());
+            },
+        };
+
+        if loop_start >= loop_end || loop_end > self.sample_count {
+            return Err(EncodeError::InvalidLoopPoints);
+        }
+
+        let shift = if align_to_frame {
+            get_next_multiple(loop_start, SAMPLES_PER_FRAME) - loop_start
+        } else {
+            0
+        };
+
+        for channel in &mut self.channels {
+            if shift > 0 {
+                channel.unroll_loop(loop_start, loop_end, shift);
+            }
+
+            channel.update_loop_points(Some((loop_start + shift, loop_end + shift)));
+        }
+
+        if shift > 0 {
+            self.sample_count = loop_end + shift;
+        }
+
+        self.looping = true;
+        self.loop_start = loop_start + shift;
+        self.loop_end = loop_end + shift;
+
+        Ok(())
+    }
+
+    /// Checks that the container's fields agree with each other and with the audio data,
+    /// returning every problem found.
+    pub fn validate(&self) -> Vec<Issue> {
+        let mut issues = vec![];
+
+        if self.channel_count != self.channels.len() {
+            issues.push(Issue::ChannelCountMismatch {
+                channel_count: self.channel_count,
+                channels: self.channels.len(),
+            });
+        }
+
+        let loop_in_bounds = self.loop_start < self.loop_end && self.loop_end <= self.sample_count;
+        if self.looping && !loop_in_bounds {
+            issues.push(Issue::LoopOutOfBounds {
+                loop_start: self.loop_start,
+                loop_end: self.loop_end,
+                sample_count: self.sample_count,
+            });
+        }
+
+        if !self.interleave_size.is_multiple_of(BYTES_PER_FRAME) {
+            issues.push(Issue::MisalignedInterleave { interleave_size: self.interleave_size });
+        }
+
+        for (channel, Channel { metadata, audio }) in self.channels.iter().enumerate() {
+            if metadata.sample_count != self.sample_count {
+                issues.push(Issue::SampleCountMismatch {
+                    channel,
+                    expected: self.sample_count,
+                    actual: metadata.sample_count,
+                });
+            }
+
+            if metadata.sample_rate != self.sample_rate {
+                issues.push(Issue::SampleRateMismatch {
+                    channel,
+                    expected: self.sample_rate,
+                    actual: metadata.sample_rate,
+                });
+            }
+
+            let nibble_count = sample_count_to_nibble_count(metadata.sample_count);
+            if metadata.nibble_count != nibble_count {
+                issues.push(Issue::NibbleCountMismatch {
+                    channel,
+                    expected: nibble_count,
+                    actual: metadata.nibble_count,
+                });
+            }
+
+            let last_sample = if self.looping { self.loop_end } else { metadata.sample_count };
+            let end_address = sample_to_nibble_address(last_sample.max(1) - 1);
+            if metadata.end_address != end_address {
+                issues.push(Issue::EndAddressMismatch {
+                    channel,
+                    expected: end_address,
+                    actual: metadata.end_address,
+                });
+            }
+
+            let byte_count = sample_count_to_byte_count(metadata.sample_count);
+            if audio.len() < byte_count {
+                issues.push(Issue::AudioTooShort {
+                    channel,
+                    expected: byte_count,
+                    actual: audio.len(),
+                });
+            }
+
+            if self.looping && loop_in_bounds {
+                let header = GcAdpcmContext::at_sample(audio, &[], self.loop_start).predictor_scale;
+                if metadata.loop_context.predictor_scale != header {
+                    issues.push(Issue::LoopContextMismatch {
+                        channel,
+                        expected: header,
+                        actual: metadata.loop_context.predictor_scale,
+                    });
+                }
+            }
+        }
+
+        issues
+    }
+
+    /// Decodes every channel, see [`Channel::decode`].
+    pub fn decode_channels(&self) -> Vec<Vec<i16>> {
+        self.channels.iter().map(Channel::decode).collect()
+    }
+
+    /// Decodes every channel into one buffer with the samples of each channel interleaved,
+    /// `sample_count` samples per channel.
+    pub fn decode_interleaved(&self) -> Vec<i16> {
+        let channels = self.decode_channels();
+        let mut pcm = Vec::with_capacity(self.sample_count * channels.len());
+
+        for i in 0..self.sample_count {
+            pcm.extend(channels.iter().map(|channel| channel.get(i).copied().unwrap_or(0)));
+        }
+
+        pcm
+    }
+
+    /// The decoded samples of each channel from `loop_start` up to `loop_end`, or `None` if
+    /// the container doesn't loop.
+    pub fn loop_region_pcm(&self) -> Option<Vec<Vec<i16>>> {
+        if !self.looping {
+            return None;
+        }
+
+        let mut channels = self.decode_channels();
+        for channel in &mut channels {
+            let end = self.loop_end.min(channel.len());
+            channel.truncate(end);
+            channel.drain(..self.loop_start.min(end));
+        }
+
+        Some(channels)
+    }
+
+    /// Decodes each channel the way a console plays it: through the intro and `loop_count`
+    /// passes of the loop, reseeding from `loop_context` at every wrap, then `fade_out` more
+    /// samples of the loop fading linearly towards silence, from full volume at the first one
+    /// down to `1 / fade_out` at the last. Without a loop this is just
+    /// [`decode_channels`](Self::decode_channels).
+    pub fn render_loops(&self, loop_count: usize, fade_out: usize) -> Vec<Vec<i16>> {
+        if !self.looping || self.loop_start >= self.loop_end {
+            return self.decode_channels();
+        }
+
+        let loop_len = self.loop_end - self.loop_start;
+        let total = self.loop_start + loop_count * loop_len + fade_out;
+
+        self.channels
+            .iter()
+            .map(|channel| {
+                let metadata = &channel.metadata;
+                let mut pcm = vec![0; total];
+                let mut context = metadata.start_context.clone();
+                let mut position = 0;
+                let mut written = 0;
+
+                while written < total {
+                    let len = (self.loop_end - position).min(total - written);
+                    let decoded = decode_gc_adpcm_from(
+                        &channel.audio,
+                        &metadata.coefficients,
+                        &mut context,
+                        position,
+                        &mut pcm[written..written + len],
+                    );
+
+                    if decoded == 0 {
+                        break;
+                    }
+
+                    written += decoded;
+                    position = self.loop_start;
+                    context = metadata.loop_context.clone();
+                }
+
+                for (i, sample) in pcm[total - fade_out..].iter_mut().enumerate() {
+                    let gain = (fade_out - i) as f64 / fade_out as f64;
+                    *sample = crate::math::round(*sample as f64 * gain) as i16;
+                }
+
+                pcm
+            })
+            .collect()
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Clone, Debug, PartialEq)]
 pub struct ChannelMetadata {
     pub sample_count: usize,
@@ -54,44 +475,266 @@ pub struct ChannelMetadata {
     pub start_address: usize,
     pub end_address: usize,
     pub current_address: usize,
-    pub coefficients: [i16; 16],
+    pub coefficients: Coefficients,
     pub gain: i16,
     pub start_context: GcAdpcmContext,
     pub loop_context: GcAdpcmContext,
 }
 
+impl ChannelMetadata {
+    /// Metadata for a channel of `sample_count` samples, with addresses laid out the way DSP
+    /// headers expect. `loop_points` is `(start, end)` with `end` exclusive, and is ignored
+    /// if the loop is empty. Both contexts start out zeroed; see [`GcAdpcmContext::at_sample`].
+    pub fn new(
+        sample_count: usize,
+        sample_rate: usize,
+        coefficients: Coefficients,
+        loop_points: Option<(usize, usize)>,
+    ) -> Self {
+        let (looping, start_address, end_address) = match loop_points {
+            Some((start, end)) if start < end => {
+                (true, sample_to_nibble_address(start), sample_to_nibble_address(end - 1))
+            },
+            _ => (
+                false,
+                sample_to_nibble_address(0),
+                sample_to_nibble_address(sample_count.max(1) - 1),
+            ),
+        };
+
+        Self {
+            sample_count,
+            nibble_count: sample_count_to_nibble_count(sample_count),
+            sample_rate,
+            looping,
+            start_address,
+            end_address,
+            current_address: sample_to_nibble_address(0),
+            coefficients,
+            gain: 0,
+            start_context: GcAdpcmContext::default(),
+            loop_context: GcAdpcmContext::default(),
+        }
+    }
+
+    /// Reads the channel header shared by IDSP and DSP files, 0x4A bytes long.
+    pub(crate) fn read(dialect: IdspDialect, bytes: &mut &[u8]) -> Result<Self, DecodeError> {
+        if bytes.len() < DSP_CHANNEL_HEADER_SIZE {
+            return Err(DecodeError::InvalidHeader);
+        }
+
+        let sample_count = dialect.get_i32(bytes) as usize;
+        let nibble_count = dialect.get_i32(bytes) as usize;
+        let sample_rate = dialect.get_i32(bytes) as usize;
+        let looping = dialect.get_i16(bytes) == 1;
+        bytes.advance(2);
+        let start_address = dialect.get_i32(bytes) as usize;
+        let end_address = dialect.get_i32(bytes) as usize;
+        let current_address = dialect.get_i32(bytes) as usize;
+        let coefficients = match dialect {
+            IdspDialect::BigEndian => Coefficients::from_be_bytes(bytes),
+            IdspDialect::LittleEndian => Coefficients::from_le_bytes(bytes),
+        }
+        .ok_or(DecodeError::InvalidHeader)?;
+        bytes.advance(32);
+
+        let gain = dialect.get_i16(bytes);
+        let start_context = dialect.read_context(bytes);
+        let loop_context = dialect.read_context(bytes);
+
+        Ok(ChannelMetadata {
+            sample_count,
+            nibble_count,
+            sample_rate,
+            looping,
+            start_address,
+            end_address,
+            current_address,
+            coefficients,
+            gain,
+            start_context,
+            loop_context,
+        })
+    }
+
+    pub(crate) fn write(&self, dialect: IdspDialect, bytes: &mut BytesMut) {
+        dialect.put_i32(bytes, self.sample_count as i32);
+        dialect.put_i32(bytes, self.nibble_count as i32);
+        dialect.put_i32(bytes, self.sample_rate as i32);
+        dialect.put_i16(bytes, self.looping as i16);
+        dialect.put_i16(bytes, 0);
+        dialect.put_i32(bytes, self.start_address as i32);
+        dialect.put_i32(bytes, self.end_address as i32);
+        dialect.put_i32(bytes, self.current_address as i32);
+        bytes.extend_from_slice(&match dialect {
+            IdspDialect::BigEndian => self.coefficients.to_be_bytes(),
+            IdspDialect::LittleEndian => self.coefficients.to_le_bytes(),
+        });
+        dialect.put_i16(bytes, self.gain);
+        dialect.write_context(&self.start_context, bytes);
+        dialect.write_context(&self.loop_context, bytes);
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Clone, PartialEq)]
 pub struct Channel {
     pub metadata: ChannelMetadata,
+    /// Serialized as base64, and left out when empty so headers can be stored on their own.
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "base64_audio", default, skip_serializing_if = "Vec::is_empty")
+    )]
     pub audio: Vec<u8>,
 }
 
-impl std::fmt::Debug for Channel {
-    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
-        fmt.debug_struct("Channel")
-            .field("metadata", &self.metadata)
-            .finish()
+#[cfg(feature = "serde")]
+mod base64_audio {
+    use alloc::{string::String, vec::Vec};
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(audio: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(audio))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(D::Error::custom)
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+impl Channel {
+    /// Wraps encoded audio, deriving the start and loop contexts from the data itself.
+    pub fn from_adpcm(
+        audio: Vec<u8>,
+        coefficients: Coefficients,
+        sample_count: usize,
+        sample_rate: usize,
+        loop_points: Option<(usize, usize)>,
+    ) -> Self {
+        let loop_context = match loop_points {
+            Some((loop_start, _)) => {
+                let mut pcm = vec![0; loop_start];
+                let mut context = GcAdpcmContext::default();
+                decode_gc_adpcm_into(&audio, &coefficients, &mut context, &mut pcm);
+
+                GcAdpcmContext::at_sample(&audio, &pcm, loop_start)
+            },
+            None => GcAdpcmContext::default(),
+        };
+
+        let metadata = ChannelMetadata {
+            start_context: GcAdpcmContext::at_sample(&audio, &[], 0),
+            loop_context,
+            ..ChannelMetadata::new(sample_count, sample_rate, coefficients, loop_points)
+        };
+
+        Self { metadata, audio }
+    }
+
+    /// Decodes the channel's `sample_count` samples, starting from its start context.
+    pub fn decode(&self) -> Vec<i16> {
+        let mut pcm = vec![0; self.metadata.sample_count];
+        let mut context = self.metadata.start_context.clone();
+        let decoded =
+            decode_gc_adpcm_into(&self.audio, &self.metadata.coefficients, &mut context, &mut pcm);
+        pcm.truncate(decoded);
+
+        pcm
+    }
+
+    /// Replaces everything from `loop_end` with the first `count` samples of the loop,
+    /// re-encoding from the start of the frame holding `loop_end`.
+    fn unroll_loop(&mut self, loop_start: usize, loop_end: usize, count: usize) {
+        let pcm = self.decode();
+        let frame_start = loop_end / SAMPLES_PER_FRAME * SAMPLES_PER_FRAME;
+        let loop_len = loop_end - loop_start;
+
+        let mut tail = pcm[frame_start..loop_end].to_vec();
+        tail.extend((0..count).map(|i| pcm[loop_start + i % loop_len]));
+
+        let mut context = if frame_start == 0 {
+            self.metadata.start_context.clone()
+        } else {
+            GcAdpcmContext::at_sample(&self.audio, &pcm, frame_start)
+        };
+
+        let byte_offset = frame_start / SAMPLES_PER_FRAME * BYTES_PER_FRAME;
+        self.audio.truncate(byte_offset);
+        self.audio.resize(byte_offset + sample_count_to_byte_count(tail.len()), 0);
+        encode_gc_adpcm_into(
+            &tail,
+            &self.metadata.coefficients,
+            &mut context,
+            &mut self.audio[byte_offset..],
+        );
+
+        self.metadata.sample_count = loop_end + count;
+    }
+
+    fn update_loop_points(&mut self, loop_points: Option<(usize, usize)>) {
+        let loop_context = match loop_points {
+            Some((loop_start, _)) => {
+                GcAdpcmContext::at_sample(&self.audio, &self.decode(), loop_start)
+            },
+            None => GcAdpcmContext::default(),
+        };
+
+        let metadata = &self.metadata;
+        self.metadata = ChannelMetadata {
+            current_address: metadata.current_address,
+            gain: metadata.gain,
+            start_context: metadata.start_context.clone(),
+            loop_context,
+            ..ChannelMetadata::new(
+                metadata.sample_count,
+                metadata.sample_rate,
+                metadata.coefficients,
+                loop_points,
+            )
+        };
+    }
+}
+
+impl fmt::Debug for Channel {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Channel").field("metadata", &self.metadata).finish()
+    }
+}
+
+/// Decoder state at a point in a GC-ADPCM stream: the frame header and the two
+/// previously decoded samples.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct GcAdpcmContext {
-    predictor_scale: i16,
-    hist_1: i16,
-    hist_2: i16,
+    pub predictor_scale: i16,
+    pub hist_1: i16,
+    pub hist_2: i16,
 }
 
 impl GcAdpcmContext {
-    pub fn read_from_buf(buf: &mut Cursor<Bytes>) -> Self {
+    /// The context for resuming decoding of `audio` at `sample`: the header of the frame
+    /// holding it and the two samples before it in `pcm`, the decoded audio.
+    pub fn at_sample(audio: &[u8], pcm: &[i16], sample: usize) -> Self {
+        let frame_start = sample / SAMPLES_PER_FRAME * BYTES_PER_FRAME;
+        let history = |offset: usize| {
+            sample.checked_sub(offset).and_then(|i| pcm.get(i)).copied().unwrap_or(0)
+        };
+
+        Self {
+            predictor_scale: audio.get(frame_start).copied().unwrap_or(0) as i16,
+            hist_1: history(1),
+            hist_2: history(2),
+        }
+    }
+
+    pub fn read_from_buf<B: Buf>(buf: &mut B) -> Self {
         let predictor_scale = buf.get_i16();
         let hist_1 = buf.get_i16();
         let hist_2 = buf.get_i16();
 
-        Self {
-            predictor_scale,
-            hist_1,
-            hist_2,
-        }
+        Self { predictor_scale, hist_1, hist_2 }
     }
 
     pub fn write_to_buf(&self, buf: &mut BytesMut) {
@@ -101,46 +744,45 @@ impl GcAdpcmContext {
     }
 }
 
-pub fn write_idsp_bytes(container: &IdspContainer) -> Result<Vec<u8>, ()> {
-    let header_size = STREAM_INFO_SIZE + container.channel_count * CHANNEL_INFO_SIZE;
-    // TODO(jake): finish calculating header and file sizes
-    // TODO(jake): verify order of header struct, seems different than C# version
+/// Fails with [`EncodeError::InvalidChannelCount`] if the container has no channels or
+/// `channel_count` doesn't match them, and [`EncodeError::InvalidHeaderSize`] if the header
+/// layout is too small for its fields.
+pub fn write_idsp_bytes(container: &IdspContainer) -> Result<Vec<u8>, EncodeError> {
+    if container.channels.is_empty() || container.channels.len() != container.channel_count {
+        return Err(EncodeError::InvalidChannelCount);
+    }
+
+    if container.header_size < MIN_STREAM_INFO_SIZE
+        || container.channel_info_size < DSP_CHANNEL_HEADER_SIZE
+    {
+        return Err(EncodeError::InvalidHeaderSize);
+    }
+
+    let audio_data_offset =
+        container.header_size + container.channel_count * container.channel_info_size;
+    // The fields are in the same order as VGAudio's IdspReader, after an unused word.
+    let dialect = container.dialect;
     let mut bytes = BytesMut::new();
     bytes.extend_from_slice(IDSP_HEADER);
-    bytes.put_i32(0);
-
-    bytes.put_i32(container.channel_count as i32);
-    bytes.put_i32(container.sample_rate as i32);
-    bytes.put_i32(container.sample_count as i32);
-    bytes.put_i32(container.loop_start as i32);
-    bytes.put_i32(container.loop_end as i32);
-    bytes.put_i32(container.interleave_size as i32);
-    bytes.put_i32(STREAM_INFO_SIZE as i32);
-    bytes.put_i32(CHANNEL_INFO_SIZE as i32);
-    bytes.put_i32(header_size as i32);
-    bytes.put_i32(container.audio_data_len() as i32);
-    bytes.resize(STREAM_INFO_SIZE, 0);
+    dialect.put_i32(&mut bytes, 0);
+
+    dialect.put_i32(&mut bytes, container.channel_count as i32);
+    dialect.put_i32(&mut bytes, container.sample_rate as i32);
+    dialect.put_i32(&mut bytes, container.sample_count as i32);
+    dialect.put_i32(&mut bytes, container.loop_start as i32);
+    dialect.put_i32(&mut bytes, container.loop_end as i32);
+    dialect.put_i32(&mut bytes, container.interleave_size as i32);
+    dialect.put_i32(&mut bytes, container.header_size as i32);
+    dialect.put_i32(&mut bytes, container.channel_info_size as i32);
+    dialect.put_i32(&mut bytes, audio_data_offset as i32);
+    dialect.put_i32(&mut bytes, container.audio_data_len() as i32);
+    bytes.resize(container.header_size, 0);
 
     for channel in container.channels.iter() {
         let mut channel_bytes = BytesMut::new();
-        let metadata = &channel.metadata;
-
-        channel_bytes.put_i32(metadata.sample_count as i32);
-        channel_bytes.put_i32(metadata.nibble_count as i32);
-        channel_bytes.put_i32(metadata.sample_rate as i32);
-        channel_bytes.put_i16(metadata.looping as i16);
-        channel_bytes.put_i16(0);
-        channel_bytes.put_i32(metadata.start_address as i32);
-        channel_bytes.put_i32(metadata.end_address as i32);
-        channel_bytes.put_i32(metadata.current_address as i32);
-        for coef in metadata.coefficients.iter() {
-            channel_bytes.put_i16(*coef);
-        }
-        channel_bytes.put_i16(metadata.gain as i16);
-        metadata.start_context.write_to_buf(&mut channel_bytes);
-        metadata.loop_context.write_to_buf(&mut channel_bytes);
-
-        channel_bytes.resize(CHANNEL_INFO_SIZE, 0);
+        channel.metadata.write(dialect, &mut channel_bytes);
+
+        channel_bytes.resize(container.channel_info_size, 0);
         bytes.unsplit(channel_bytes);
     }
 
@@ -153,6 +795,7 @@ pub fn write_idsp_bytes(container: &IdspContainer) -> Result<Vec<u8>, ()> {
     Ok(bytes.to_vec())
 }
 
+#[cfg(feature = "std")]
 pub fn read_idsp<P: AsRef<Path>>(file_path: P) -> Result<IdspContainer, DecodeError> {
     let mut file = File::open(file_path)?;
     let mut bytes = Vec::new();
@@ -162,77 +805,54 @@ pub fn read_idsp<P: AsRef<Path>>(file_path: P) -> Result<IdspContainer, DecodeEr
 }
 
 pub fn read_idsp_bytes(original_bytes: &[u8]) -> Result<IdspContainer, DecodeError> {
-    if &original_bytes[..IDSP_HEADER.len()] != IDSP_HEADER {
-        return Err(DecodeError::InvalidHeader);
+    let dialect = IdspDialect::detect(original_bytes).ok_or(DecodeError::InvalidHeader)?;
+    let mut bytes = &original_bytes[8..];
+
+    let channel_count = dialect.get_i32(&mut bytes) as usize;
+    let sample_rate = dialect.get_i32(&mut bytes) as usize;
+    let sample_count = dialect.get_i32(&mut bytes) as usize;
+    let loop_start = dialect.get_i32(&mut bytes) as usize;
+    let loop_end = dialect.get_i32(&mut bytes) as usize;
+    let interleave_size = dialect.get_i32(&mut bytes) as usize;
+    let header_size = dialect.get_i32(&mut bytes) as usize;
+    let channel_info_size = dialect.get_i32(&mut bytes) as usize;
+    let audio_data_offset = dialect.get_i32(&mut bytes) as usize;
+    let audio_data_length = dialect.get_i32(&mut bytes);
+
+    // Negative lengths and sizes wrap around to huge values when cast.
+    if audio_data_length < 0 || sample_count > i32::MAX as usize {
+        return Err(DecodeError::InvalidAudioLength);
     }
+    let audio_data_length = audio_data_length as usize;
 
-    let mut bytes = Cursor::new(Bytes::copy_from_slice(original_bytes));
-
-    bytes.advance(8);
+    if interleave_size > i32::MAX as usize || (interleave_size == 0 && audio_data_length == 0) {
+        return Err(DecodeError::InvalidHeader);
+    }
 
-    let channel_count = bytes.get_i32() as usize;
-    let sample_rate = bytes.get_i32() as usize;
-    let sample_count = bytes.get_i32() as usize;
-    let loop_start = bytes.get_i32() as usize;
-    let loop_end = bytes.get_i32() as usize;
-    let interleave_size = bytes.get_i32() as usize;
-    let header_size = bytes.get_i32() as usize;
-    let channel_info_size = bytes.get_i32() as usize;
-    let audio_data_offset = bytes.get_i32() as usize;
-    let audio_data_length = bytes.get_i32() as usize;
+    if sample_count_to_byte_count(sample_count) > audio_data_length {
+        return Err(DecodeError::InvalidAudioLength);
+    }
 
     let mut metadatas = vec![];
     for i in 0..channel_count {
-        bytes.set_position((header_size + i * channel_info_size) as u64);
-
-        let sample_count = bytes.get_i32() as usize;
-        let nibble_count = bytes.get_i32() as usize;
-        let sample_rate = bytes.get_i32() as usize;
-        let looping = bytes.get_i16() == 1;
-        bytes.advance(2);
-        let start_address = bytes.get_i32() as usize;
-        let end_address = bytes.get_i32() as usize;
-        let current_address = bytes.get_i32() as usize;
-        let mut coefficients = [0; 16];
-
-        for c in &mut coefficients {
-            *c = bytes.get_i16();
-        }
-
-        let gain = bytes.get_i16();
-        let start_context = GcAdpcmContext::read_from_buf(&mut bytes);
-        let loop_context = GcAdpcmContext::read_from_buf(&mut bytes);
-
-        let channel = ChannelMetadata {
-            sample_count,
-            nibble_count,
-            sample_rate,
-            looping,
-            start_address,
-            end_address,
-            current_address,
-            coefficients,
-            gain,
-            start_context,
-            loop_context,
-        };
+        let channel_offset = i
+            .checked_mul(channel_info_size)
+            .and_then(|offset| offset.checked_add(header_size))
+            .ok_or(DecodeError::InvalidHeader)?;
+        let mut bytes = original_bytes.get(channel_offset..).ok_or(DecodeError::InvalidHeader)?;
 
+        let channel = ChannelMetadata::read(dialect, &mut bytes)?;
         metadatas.push(channel);
     }
 
     let looping = metadatas.iter().any(|c| c.looping);
 
     // Read audio data
-    bytes.set_position(audio_data_offset as u64);
-    let interleave: usize = if interleave_size == 0 {
-        audio_data_length
-    } else {
-        interleave_size
-    };
+    let interleave: usize = if interleave_size == 0 { audio_data_length } else { interleave_size };
 
     let audio_data = deinterleave(
-        &mut bytes,
-        channel_count * audio_data_length,
+        original_bytes.get(audio_data_offset..).ok_or(DecodeError::InvalidAudioLength)?,
+        channel_count.checked_mul(audio_data_length).ok_or(DecodeError::InvalidAudioLength)?,
         interleave,
         channel_count,
         Some(sample_count_to_byte_count(sample_count)),
@@ -240,10 +860,16 @@ pub fn read_idsp_bytes(original_bytes: &[u8]) -> Result<IdspContainer, DecodeErr
 
     let channels = metadatas
         .into_iter()
-        .zip(audio_data.into_iter())
+        .zip(audio_data)
         .map(|(metadata, audio)| Channel { metadata, audio })
         .collect();
 
+    let padding = if audio_data_length == get_next_multiple(audio_data_length, interleave) {
+        BlockPadding::FullBlock
+    } else {
+        BlockPadding::Frame
+    };
+
     let container = IdspContainer {
         looping,
         channel_count,
@@ -252,20 +878,26 @@ pub fn read_idsp_bytes(original_bytes: &[u8]) -> Result<IdspContainer, DecodeErr
         loop_start,
         loop_end,
         interleave_size,
+        padding,
         header_size,
+        channel_info_size,
+        dialect,
         channels,
     };
 
     Ok(container)
 }
 
-fn interleave(
-    inputs: &Vec<Channel>,
+pub(crate) fn interleave(
+    inputs: &[Channel],
     interleave_size: usize,
     output_size: Option<usize>,
 ) -> Vec<u8> {
     let input_size = inputs[0].audio.len();
     let output_size = output_size.unwrap_or(input_size);
+    // An interleave size of 0 stores each channel as a single block.
+    let interleave_size =
+        if interleave_size == 0 { output_size.max(input_size).max(1) } else { interleave_size };
 
     let input_count = inputs.len();
     let in_block_count = input_size.divide_by_round_up(interleave_size);
@@ -277,45 +909,37 @@ fn interleave(
     let mut output = vec![0u8; output_size * input_count];
 
     for b in 0..blocks_to_copy {
-        let current_input_interleave_size = if b == in_block_count - 1 {
-            last_input_interleave_size
-        } else {
-            interleave_size
-        };
-        let current_output_interleave_size = if b == out_block_count - 1 {
-            last_output_interleave_size
-        } else {
-            interleave_size
-        };
+        let current_input_interleave_size =
+            if b == in_block_count - 1 { last_input_interleave_size } else { interleave_size };
+        let current_output_interleave_size =
+            if b == out_block_count - 1 { last_output_interleave_size } else { interleave_size };
         let bytes_to_copy = current_input_interleave_size.min(current_output_interleave_size);
 
-        for i in 0..input_count {
+        for (i, input) in inputs.iter().enumerate() {
             let input_index = interleave_size * b;
             let output_index =
                 interleave_size * b * input_count + current_output_interleave_size * i;
             output[output_index..output_index + bytes_to_copy]
-                .copy_from_slice(&inputs[i].audio[input_index..input_index + bytes_to_copy]);
+                .copy_from_slice(&input.audio[input_index..input_index + bytes_to_copy]);
         }
     }
 
     output
 }
 
-fn deinterleave(
-    bytes: &mut Cursor<Bytes>,
+pub(crate) fn deinterleave(
+    mut bytes: &[u8],
     len: usize,
     interleave_size: usize,
     output_count: usize,
     output_size: Option<usize>,
 ) -> Result<Vec<Vec<u8>>, DecodeError> {
-    let remaining_length = bytes.get_ref().len() - bytes.position() as usize;
-
-    if remaining_length < len {
+    if bytes.len() < len {
         // Specified length is greater than the number of bytes remaining in the Stream
         return Err(DecodeError::InvalidAudioLength);
     }
 
-    if len % output_count != 0 {
+    if !len.is_multiple_of(output_count) {
         // The input length must be divisible by the number of outputs.
         return Err(DecodeError::InvalidAudioLength);
     }
@@ -323,6 +947,10 @@ fn deinterleave(
     let input_size = len / output_count;
     let output_size = output_size.unwrap_or(input_size);
 
+    if input_size == 0 || output_size == 0 || interleave_size == 0 {
+        return Err(DecodeError::InvalidAudioLength);
+    }
+
     let in_block_count = input_size.divide_by_round_up(interleave_size);
     let out_block_count = output_size.divide_by_round_up(interleave_size);
     let last_input_interleave_size = input_size - (in_block_count - 1) * interleave_size;
@@ -332,23 +960,17 @@ fn deinterleave(
     let mut outputs = vec![vec![0; output_size]; output_count];
 
     for b in 0..blocks_to_copy {
-        let current_input_interlave_size = if b == in_block_count - 1 {
-            last_input_interleave_size
-        } else {
-            interleave_size
-        };
+        let current_input_interlave_size =
+            if b == in_block_count - 1 { last_input_interleave_size } else { interleave_size };
 
-        let current_output_interleave_size = if b == out_block_count - 1 {
-            last_output_interleave_size
-        } else {
-            interleave_size
-        };
+        let current_output_interleave_size =
+            if b == out_block_count - 1 { last_output_interleave_size } else { interleave_size };
 
         let bytes_to_copy = current_input_interlave_size.min(current_output_interleave_size);
 
-        for o in 0..output_count {
+        for output in outputs.iter_mut() {
             let offset = interleave_size * b;
-            bytes.copy_to_slice(&mut outputs[o][offset..(offset + bytes_to_copy)]);
+            bytes.copy_to_slice(&mut output[offset..(offset + bytes_to_copy)]);
 
             if bytes_to_copy < current_input_interlave_size {
                 bytes.advance(current_input_interlave_size - bytes_to_copy);
@@ -362,7 +984,7 @@ fn deinterleave(
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::decode::decode_gc_adpcm;
+    use crate::{decode::decode_gc_adpcm, encode::encode_gc_adpcm};
     use wav::{BitDepth, Header};
 
     #[test]
@@ -378,19 +1000,13 @@ mod test {
 
         assert_eq!(idsp_file.channels.len(), 1);
 
-        let decoded: Vec<i16> = decode_gc_adpcm(
-            &idsp_file.channels[0].audio,
-            &idsp_file.channels[0].metadata.coefficients,
-        );
+        let decoded: Vec<i16> = idsp_file.channels[0].decode();
+        assert_eq!(decoded.len(), idsp_file.sample_count);
 
-        let header = Header::new(
-            1,
-            idsp_file.channels.len() as u16,
-            idsp_file.sample_rate as u32,
-            16,
-        );
+        let header =
+            Header::new(1, idsp_file.channels.len() as u16, idsp_file.sample_rate as u32, 16);
 
-        let mut output_file = std::fs::File::create("lol.wav").unwrap();
+        let mut output_file = std::fs::File::create(std::env::temp_dir().join("lol.wav")).unwrap();
         wav::write(header, BitDepth::Sixteen(decoded), &mut output_file).unwrap();
     }
 
@@ -407,5 +1023,366 @@ mod test {
         let decoded_idsp_file = read_idsp_bytes(&encoded_bytes).unwrap();
 
         assert_eq!(idsp_file, decoded_idsp_file);
+
+        for &channel_count in &[0, 2] {
+            let mismatched = IdspContainer { channel_count, ..idsp_file.clone() };
+            assert!(matches!(write_idsp_bytes(&mismatched), Err(EncodeError::InvalidChannelCount)));
+        }
+        let empty = IdspContainer { channel_count: 0, channels: vec![], ..idsp_file };
+        assert!(matches!(write_idsp_bytes(&empty), Err(EncodeError::InvalidChannelCount)));
+    }
+
+    #[test]
+    fn test_short_roundtrip() {
+        for &sample_count in &[1, 100, 300, 1000] {
+            let pcm = (0..sample_count).map(|i| (i * 97 % 4000) as i16 - 2000).collect();
+            let container = crate::builder::IdspBuilder::new(32000).channel(pcm).build().unwrap();
+
+            let bytes = write_idsp_bytes(&container).unwrap();
+            assert_eq!(read_idsp_bytes(&bytes).unwrap(), container, "{} samples", sample_count);
+        }
+    }
+
+    #[test]
+    fn test_corrupt_header() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+
+        let with_field = |offset: usize, value: u32| {
+            let mut bytes = idsp_bytes.to_vec();
+            bytes[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+            read_idsp_bytes(&bytes)
+        };
+
+        // Header size, channel info size, audio data offset and length.
+        for &offset in &[0x20, 0x24, 0x28, 0x2C] {
+            for &value in &[0, 0x2C, 0x7FFF_FF00, 0xFFFF_FFFF] {
+                assert!(with_field(offset, value).is_err(), "{:#x} at {:#x}", value, offset);
+            }
+        }
+
+        // Unblocked audio with no length to take as the block size.
+        let mut unblocked = idsp_bytes.to_vec();
+        unblocked[0x1C..0x20].copy_from_slice(&[0; 4]);
+        unblocked[0x2C..0x30].copy_from_slice(&[0; 4]);
+        assert!(read_idsp_bytes(&unblocked).is_err());
+
+        // Whatever the other fields hold, reading fails cleanly instead of panicking.
+        for offset in (0x08..0x30).step_by(4) {
+            for &value in &[0, 1, 0x7FFF_FF00, 0xFFFF_FFFF] {
+                let _ = with_field(offset, value);
+            }
+        }
+    }
+
+    #[test]
+    fn test_little_endian_dialect() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+        assert_eq!(IdspDialect::detect(idsp_bytes), Some(IdspDialect::BigEndian));
+
+        let little_endian =
+            IdspContainer { dialect: IdspDialect::LittleEndian, ..idsp_file.clone() };
+        let bytes = write_idsp_bytes(&little_endian).unwrap();
+        assert_eq!(&bytes[8..12], &[1, 0, 0, 0]);
+        assert_eq!(&bytes[..STREAM_INFO_SIZE], {
+            // Only the byte order of the header differs.
+            let mut swapped = idsp_bytes[..STREAM_INFO_SIZE].to_vec();
+            for field in swapped[4..0x30].chunks_mut(4) {
+                field.reverse();
+            }
+            swapped
+        });
+
+        let read = read_idsp_bytes(&bytes).unwrap();
+        assert_eq!(read, little_endian);
+        assert_eq!(write_idsp_bytes(&read).unwrap(), bytes);
+
+        let mut garbage = idsp_bytes.to_vec();
+        garbage[8..12].copy_from_slice(&[0x7F; 4]);
+        assert_eq!(IdspDialect::detect(&garbage), None);
+        assert!(matches!(read_idsp_bytes(&garbage), Err(DecodeError::InvalidHeader)));
+    }
+
+    #[test]
+    fn test_header_layout() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+        let padded = IdspContainer {
+            channel_count: 2,
+            channels: vec![idsp_file.channels[0].clone(), idsp_file.channels[0].clone()],
+            header_size: 0x50,
+            channel_info_size: 0x70,
+            ..idsp_file.clone()
+        };
+
+        let bytes = write_idsp_bytes(&padded).unwrap();
+        assert_eq!(&bytes[0x20..0x2C], &[0, 0, 0, 0x50, 0, 0, 0, 0x70, 0, 0, 0x01, 0x30]);
+        assert_eq!(bytes[0x50..0x50 + DSP_CHANNEL_HEADER_SIZE], bytes[0xC0..0x10A]);
+
+        let read = read_idsp_bytes(&bytes).unwrap();
+        assert_eq!(read, padded);
+        assert_eq!(write_idsp_bytes(&read).unwrap(), bytes);
+
+        for &(header_size, channel_info_size) in &[(0x2C, 0x60), (0x40, 0x40)] {
+            let container = IdspContainer { header_size, channel_info_size, ..padded.clone() };
+            assert!(matches!(write_idsp_bytes(&container), Err(EncodeError::InvalidHeaderSize)));
+        }
+    }
+
+    #[test]
+    fn test_reblock() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+        let stereo = IdspContainer {
+            channel_count: 2,
+            channels: vec![idsp_file.channels[0].clone(), idsp_file.channels[0].clone()],
+            ..idsp_file.clone()
+        };
+        let byte_count = sample_count_to_byte_count(stereo.sample_count);
+        let header_len = STREAM_INFO_SIZE + 2 * CHANNEL_INFO_SIZE;
+
+        for &(interleave_size, padding, audio_data_len) in &[
+            (0x8000, BlockPadding::FullBlock, 0x8000),
+            (0x8000, BlockPadding::Frame, get_next_multiple(byte_count, BYTES_PER_FRAME)),
+            (0, BlockPadding::FullBlock, byte_count),
+        ] {
+            let mut reblocked = stereo.clone();
+            reblocked.reblock(interleave_size, padding).unwrap();
+            assert_eq!(reblocked.audio_data_len(), audio_data_len);
+
+            let bytes = write_idsp_bytes(&reblocked).unwrap();
+            assert_eq!(bytes.len(), header_len + 2 * audio_data_len);
+
+            let read = read_idsp_bytes(&bytes).unwrap();
+            assert_eq!((read.interleave_size, read.padding), (interleave_size, padding));
+            assert_eq!(read.decode_channels(), stereo.decode_channels());
+        }
+
+        let mut reblocked = stereo;
+        assert!(matches!(
+            reblocked.reblock(12, BlockPadding::FullBlock),
+            Err(EncodeError::InvalidBlockSize)
+        ));
+    }
+
+    #[test]
+    fn test_validate() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+        assert_eq!(idsp_file.validate(), vec![]);
+
+        let mut broken = idsp_file.clone();
+        broken.channel_count = 2;
+        broken.interleave_size = 12;
+        broken.looping = true;
+        broken.loop_start = 100;
+        broken.loop_end = broken.sample_count + 1;
+        broken.channels[0].metadata.sample_rate = 32000;
+        broken.channels[0].metadata.nibble_count += 1;
+
+        assert_eq!(
+            broken.validate(),
+            vec![
+                Issue::ChannelCountMismatch { channel_count: 2, channels: 1 },
+                Issue::LoopOutOfBounds {
+                    loop_start: 100,
+                    loop_end: idsp_file.sample_count + 1,
+                    sample_count: idsp_file.sample_count,
+                },
+                Issue::MisalignedInterleave { interleave_size: 12 },
+                Issue::SampleRateMismatch { channel: 0, expected: 48000, actual: 32000 },
+                Issue::NibbleCountMismatch { channel: 0, expected: 53755, actual: 53756 },
+                Issue::EndAddressMismatch { channel: 0, expected: 53755, actual: 53754 },
+            ]
+        );
+
+        // A loop whose context was taken from the wrong frame.
+        let channel = &idsp_file.channels[0];
+        let mut looping = IdspContainer {
+            looping: true,
+            loop_start: 1000,
+            loop_end: idsp_file.sample_count,
+            channels: vec![Channel::from_adpcm(
+                channel.audio.clone(),
+                channel.metadata.coefficients,
+                idsp_file.sample_count,
+                idsp_file.sample_rate,
+                Some((1000, idsp_file.sample_count)),
+            )],
+            ..idsp_file
+        };
+        assert_eq!(looping.validate(), vec![]);
+
+        let header = looping.channels[0].audio[1000 / SAMPLES_PER_FRAME * BYTES_PER_FRAME] as i16;
+        looping.channels[0].metadata.loop_context.predictor_scale = header ^ 1;
+        assert_eq!(
+            looping.validate(),
+            vec![Issue::LoopContextMismatch { channel: 0, expected: header, actual: header ^ 1 }]
+        );
+    }
+
+    #[test]
+    fn test_set_loop_points() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+        let original = &idsp_file.channels[0];
+        let pcm = original.decode();
+
+        // Without alignment the audio is untouched.
+        let mut unaligned = idsp_file.clone();
+        unaligned.set_loop_points(Some((1000, 40000)), false).unwrap();
+        let channel = &unaligned.channels[0];
+
+        assert_eq!(channel.audio, original.audio);
+        assert_eq!(channel.metadata.start_address, sample_to_nibble_address(1000));
+        assert_eq!(
+            channel.metadata.loop_context,
+            GcAdpcmContext::at_sample(&channel.audio, &pcm, 1000)
+        );
+        assert_eq!(unaligned.validate(), vec![]);
+
+        // Aligning moves the loop to 1008..40008 and only re-encodes from frame 2857 on.
+        let mut aligned = idsp_file.clone();
+        aligned.set_loop_points(Some((1000, 40000)), true).unwrap();
+        let channel = &aligned.channels[0];
+        let unchanged = 40000 / SAMPLES_PER_FRAME * BYTES_PER_FRAME;
+
+        assert_eq!(
+            (aligned.loop_start, aligned.loop_end, aligned.sample_count),
+            (1008, 40008, 40008)
+        );
+        assert_eq!(channel.audio[..unchanged], original.audio[..unchanged]);
+        assert_eq!(
+            channel.metadata.loop_context.predictor_scale,
+            channel.audio[72 * BYTES_PER_FRAME] as i16
+        );
+        assert_eq!(aligned.validate(), vec![]);
+
+        let decoded = channel.decode();
+        let tail_error = (0..8).map(|i| (decoded[40000 + i] - pcm[1000 + i]).abs()).max().unwrap();
+        // The frame holding the loop seam jumps by ~2500, so it can't be encoded exactly.
+        assert!(tail_error < 512, "unrolled loop start is off by {}", tail_error);
+
+        aligned.set_loop_points(None, false).unwrap();
+        assert!(!aligned.looping && !aligned.channels[0].metadata.looping);
+        assert_eq!(aligned.validate(), vec![]);
+
+        assert!(matches!(
+            aligned.set_loop_points(Some((5, aligned.sample_count + 1)), false),
+            Err(EncodeError::InvalidLoopPoints)
+        ));
+
+        let coefficients = original.metadata.coefficients;
+        for &loop_points in &[(0, 0), (100, 50)] {
+            assert_eq!(
+                ChannelMetadata::new(1000, 48000, coefficients, Some(loop_points)),
+                ChannelMetadata::new(1000, 48000, coefficients, None)
+            );
+        }
+    }
+
+    #[test]
+    fn test_render_loops() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let mut idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+        let pcm = idsp_file.channels[0].decode();
+
+        assert_eq!(idsp_file.render_loops(3, 100), vec![pcm.clone()]);
+
+        // A loop starting in the middle of a frame.
+        idsp_file.set_loop_points(Some((1000, 20000)), false).unwrap();
+        let rendered = idsp_file.render_loops(3, 100).remove(0);
+
+        assert_eq!(rendered.len(), 1000 + 3 * 19000 + 100);
+        assert_eq!(rendered[..20000], pcm[..20000]);
+        assert_eq!(rendered[20000..39000], pcm[1000..20000]);
+        assert_eq!(rendered[39000..58000], pcm[1000..20000]);
+        assert_eq!(rendered[58000], pcm[1000]);
+        assert_eq!(rendered[58001], crate::math::round(pcm[1001] as f64 * 0.99) as i16);
+        assert_eq!(rendered[58099], crate::math::round(pcm[1099] as f64 * 0.01) as i16);
+
+        // Audio that ends before the loop end goes straight back to the loop start.
+        idsp_file.channels[0].audio.truncate(100 * BYTES_PER_FRAME);
+        let rendered = idsp_file.render_loops(3, 100).remove(0);
+        assert_eq!(rendered[..1400], pcm[..1400]);
+        assert_eq!(rendered[1400..1800], pcm[1000..1400]);
+        assert_eq!(rendered[1800..2200], pcm[1000..1400]);
+    }
+
+    #[test]
+    fn test_decode_accessors() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+        let channel = &idsp_file.channels[0];
+        let pcm = decode_gc_adpcm(&channel.audio, &channel.metadata.coefficients);
+
+        let pcm = &pcm[..idsp_file.sample_count];
+        assert_eq!(channel.decode(), pcm);
+        assert_eq!(idsp_file.decode_interleaved(), pcm);
+
+        // A stereo container looping over part of the stream.
+        let coefficients = channel.metadata.coefficients;
+        let quiet: Vec<i16> = pcm.iter().map(|sample| sample / 2).collect();
+        let quiet_audio = encode_gc_adpcm(&quiet, &coefficients).data;
+        let loop_points = Some((1000, 2000));
+
+        let container = IdspContainer {
+            looping: true,
+            channel_count: 2,
+            loop_start: 1000,
+            loop_end: 2000,
+            channels: vec![
+                Channel::from_adpcm(
+                    channel.audio.clone(),
+                    coefficients,
+                    idsp_file.sample_count,
+                    idsp_file.sample_rate,
+                    loop_points,
+                ),
+                Channel::from_adpcm(
+                    quiet_audio,
+                    coefficients,
+                    idsp_file.sample_count,
+                    idsp_file.sample_rate,
+                    loop_points,
+                ),
+            ],
+            ..idsp_file.clone()
+        };
+
+        let channels = container.decode_channels();
+        let interleaved = container.decode_interleaved();
+        assert_eq!(interleaved.len(), idsp_file.sample_count * 2);
+        assert_eq!(interleaved[20], channels[0][10]);
+        assert_eq!(interleaved[21], channels[1][10]);
+
+        let loop_region = container.loop_region_pcm().unwrap();
+        assert_eq!(loop_region[0], channels[0][1000..2000]);
+        assert_eq!(loop_region[1], channels[1][1000..2000]);
+        assert!(IdspContainer { looping: false, ..container }.loop_region_pcm().is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_serde() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+
+        let json = serde_json::to_string(&idsp_file).unwrap();
+        assert_eq!(serde_json::from_str::<IdspContainer>(&json).unwrap(), idsp_file);
+
+        // Dump the headers alone, edit them and write them back out with the original audio.
+        let json = serde_json::to_string_pretty(&idsp_file.without_audio()).unwrap();
+        assert!(!json.contains("\"audio\""));
+        let json = json.replace("\"sample_rate\": 48000", "\"sample_rate\": 44100");
+
+        let mut edited: IdspContainer = serde_json::from_str(&json).unwrap();
+        assert!(edited.channels[0].audio.is_empty());
+        edited.copy_audio_from(&idsp_file).unwrap();
+
+        let written = read_idsp_bytes(&write_idsp_bytes(&edited).unwrap()).unwrap();
+        assert_eq!(written.sample_rate, 44100);
+        assert_eq!(written.channels[0].metadata.sample_rate, 44100);
+        assert_eq!(written.channels[0].audio, idsp_file.channels[0].audio);
     }
 }
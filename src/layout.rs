@@ -0,0 +1,163 @@
+//! Splitting, merging, reordering and downmixing the channels of an [`IdspContainer`].
+
+use crate::{
+    coefficients::Coefficients,
+    encode::encode_gc_adpcm,
+    idsp::{Channel, EncodeError, IdspContainer},
+    math::{clamp_16, round},
+};
+use alloc::{vec, vec::Vec};
+
+impl IdspContainer {
+    /// A mono container for each channel.
+    pub fn split_channels(&self) -> Vec<IdspContainer> {
+        self.channels.iter().map(|channel| self.with_channels(vec![channel.clone()])).collect()
+    }
+
+    /// Combines the channels of `containers` into one container, in order. They must agree
+    /// on sample rate, sample count and loop points. The interleave size of the first
+    /// container is kept.
+    pub fn merge(containers: &[IdspContainer]) -> Result<IdspContainer, EncodeError> {
+        let first = containers.first().ok_or(EncodeError::InvalidChannelCount)?;
+
+        let matches = |other: &IdspContainer| {
+            other.sample_rate == first.sample_rate
+                && other.sample_count == first.sample_count
+                && other.looping == first.looping
+                && (!first.looping
+                    || (other.loop_start, other.loop_end) == (first.loop_start, first.loop_end))
+        };
+
+        if !containers.iter().all(matches) {
+            return Err(EncodeError::MismatchedChannels);
+        }
+
+        let channels =
+            containers.iter().flat_map(|container| container.channels.iter().cloned()).collect();
+
+        Ok(first.with_channels(channels))
+    }
+
+    /// A container holding the channels at `indices`, in that order. Channels can be dropped,
+    /// reordered or repeated.
+    pub fn select_channels(&self, indices: &[usize]) -> Result<IdspContainer, EncodeError> {
+        let channels = indices
+            .iter()
+            .map(|&i| self.channels.get(i).cloned().ok_or(EncodeError::InvalidChannelCount))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if channels.is_empty() {
+            return Err(EncodeError::InvalidChannelCount);
+        }
+
+        Ok(self.with_channels(channels))
+    }
+
+    /// Mixes every channel down to one by averaging them. This is the only layout change that
+    /// re-encodes the audio, with coefficients computed for the mix.
+    pub fn downmix(&self) -> IdspContainer {
+        if self.channels.len() <= 1 {
+            return self.clone();
+        }
+
+        let decoded = self.decode_channels();
+        let mixed: Vec<i16> = (0..self.sample_count)
+            .map(|i| {
+                let sum: f64 =
+                    decoded.iter().map(|pcm| pcm.get(i).copied().unwrap_or(0) as f64).sum();
+                clamp_16(round(sum / decoded.len() as f64) as i32)
+            })
+            .collect();
+
+        let coefficients = Coefficients::from(&mixed);
+        let audio = encode_gc_adpcm(&mixed, &coefficients);
+        let loop_points = if self.looping { Some((self.loop_start, self.loop_end)) } else { None };
+
+        self.with_channels(vec![Channel::from_adpcm(
+            audio.data,
+            coefficients,
+            self.sample_count,
+            self.sample_rate,
+            loop_points,
+        )])
+    }
+
+    fn with_channels(&self, channels: Vec<Channel>) -> IdspContainer {
+        IdspContainer {
+            looping: self.looping,
+            channel_count: channels.len(),
+            sample_rate: self.sample_rate,
+            loop_start: self.loop_start,
+            loop_end: self.loop_end,
+            sample_count: self.sample_count,
+            interleave_size: self.interleave_size,
+            padding: self.padding,
+            header_size: self.header_size,
+            channel_info_size: self.channel_info_size,
+            dialect: self.dialect,
+            channels,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::idsp::{read_idsp_bytes, write_idsp_bytes};
+
+    #[test]
+    fn test_split_and_merge() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+
+        let stereo = IdspContainer::merge(&[idsp_file.clone(), idsp_file.clone()]).unwrap();
+        assert_eq!(stereo.channel_count, 2);
+        assert_eq!(stereo.validate(), vec![]);
+        write_idsp_bytes(&stereo).unwrap();
+
+        let split = stereo.split_channels();
+        assert_eq!(split, vec![idsp_file.clone(), idsp_file.clone()]);
+
+        let mut shorter = idsp_file.clone();
+        shorter.sample_count -= 1;
+        assert!(matches!(
+            IdspContainer::merge(&[idsp_file, shorter]),
+            Err(EncodeError::MismatchedChannels)
+        ));
+    }
+
+    #[test]
+    fn test_select_and_downmix() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+        let mut quiet = idsp_file.clone();
+        quiet.channels[0].audio = encode_gc_adpcm(
+            &idsp_file.channels[0].decode().iter().map(|s| s / 2).collect::<Vec<_>>(),
+            &idsp_file.channels[0].metadata.coefficients,
+        )
+        .data;
+
+        let stereo = IdspContainer::merge(&[idsp_file.clone(), quiet.clone()]).unwrap();
+
+        let swapped = stereo.select_channels(&[1, 0]).unwrap();
+        assert_eq!(swapped.channels[0], quiet.channels[0]);
+        assert_eq!(swapped.channels[1], idsp_file.channels[0]);
+        assert!(matches!(stereo.select_channels(&[2]), Err(EncodeError::InvalidChannelCount)));
+        assert!(matches!(stereo.select_channels(&[]), Err(EncodeError::InvalidChannelCount)));
+
+        let mono = stereo.downmix();
+        assert_eq!(mono.channel_count, 1);
+        assert_eq!(mono.validate(), vec![]);
+
+        // The mix is 3/4 of the original, give or take the encoding error.
+        let loud = idsp_file.channels[0].decode();
+        let mixed = mono.channels[0].decode();
+        let error: i64 = loud
+            .iter()
+            .zip(&mixed)
+            .map(|(&a, &b)| (a as i64 * 3 / 4 - b as i64).abs())
+            .sum::<i64>()
+            / loud.len() as i64;
+        assert!(error < 64, "average error of {}", error);
+    }
+}
@@ -1,14 +1,43 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+pub mod brstm;
+pub mod builder;
 pub mod coefficients;
+pub mod container;
 pub mod decode;
+pub mod dsp;
 pub mod encode;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod format;
+pub mod genh;
+pub mod hps;
 pub mod idsp;
+pub mod layout;
 pub mod math;
+pub mod nus3audio;
+pub mod nus3bank;
+pub mod resample;
+pub mod yamaha;
 
 pub use crate::{
-    coefficients::Coefficients,
-    decode::decode_gc_adpcm,
-    encode::encode_gc_adpcm,
-    idsp::{read_idsp_bytes, write_idsp_bytes, IdspContainer},
+    brstm::{read_brstm_bytes, write_brstm_bytes},
+    builder::{IdspBuilder, LoopAlignment},
+    coefficients::{CoefficientDiagnostics, Coefficients},
+    container::{AudioContainer, GcAdpcmAudio},
+    decode::{decode_gc_adpcm, decode_gc_adpcm_from, decode_gc_adpcm_into},
+    dsp::{read_dsp_bytes, read_dsp_channels, write_dsp_bytes, write_dsp_channels},
+    encode::{encode_gc_adpcm, encode_gc_adpcm_into},
+    format::{detect_format, Format},
+    genh::{read_genh_bytes, read_raw_bytes, write_genh_bytes, RawParameters},
+    hps::{read_hps_bytes, write_hps_bytes},
+    idsp::{read_idsp_bytes, write_idsp_bytes, GcAdpcmContext, IdspContainer},
+    nus3audio::{read_nus3audio_bytes, write_nus3audio_bytes, Nus3Audio},
+    nus3bank::{read_nus3bank_bytes, write_nus3bank_bytes, Nus3Bank},
+    resample::{resample, ResampleQuality},
+    yamaha::{decode_yamaha_adpcm, encode_yamaha_adpcm, YamahaContext},
 };
 
 const SAMPLES_PER_FRAME: usize = 14;
@@ -25,7 +54,6 @@ struct CodecParameters {
 mod test {
     use crate::{
         coefficients::Coefficients,
-        decode::decode_gc_adpcm,
         encode::encode_gc_adpcm,
         idsp::{read_idsp_bytes, write_idsp_bytes, IdspContainer},
     };
@@ -37,14 +65,11 @@ mod test {
 
         assert_eq!(idsp_file.channels.len(), 1);
 
-        let wav_pcm: Vec<i16> = decode_gc_adpcm(
-            &idsp_file.channels[0].audio,
-            &idsp_file.channels[0].metadata.coefficients,
-        );
+        let wav_pcm: Vec<i16> = idsp_file.channels[0].decode();
 
         let coefficients = Coefficients::from(&wav_pcm);
 
-        let gcadpcm = encode_gc_adpcm(&wav_pcm, &*coefficients);
+        let gcadpcm = encode_gc_adpcm(&wav_pcm, &coefficients);
 
         write_idsp_bytes(&IdspContainer { sample_count: gcadpcm.sample_count, ..idsp_file })
             .unwrap();
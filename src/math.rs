@@ -7,7 +7,7 @@ pub trait DivideByRoundUp {
 
 impl DivideByRoundUp for usize {
     fn divide_by_round_up(&self, divisor: usize) -> usize {
-        (*self as f64 / divisor as f64).ceil() as usize
+        self.div_ceil(divisor)
     }
 
     fn divide_by_2_round_up(&self) -> usize {
@@ -27,6 +27,41 @@ pub fn clamp_16(value: i32) -> i16 {
     value as i16
 }
 
+/// Rounds half away from zero, like `f64::round`, which is unavailable without `std`.
+pub fn round(value: f64) -> f64 {
+    let truncated = value as i64 as f64;
+    let fraction = value - truncated;
+
+    if fraction >= 0.5 {
+        truncated + 1.0
+    } else if fraction <= -0.5 {
+        truncated - 1.0
+    } else {
+        truncated
+    }
+}
+
+/// `f64::sin` for `no_std`, accurate to about 1e-8, from a Taylor series around zero.
+pub fn sin(value: f64) -> f64 {
+    use core::f64::consts::PI;
+
+    let x = value - 2.0 * PI * round(value / (2.0 * PI));
+    let x_squared = x * x;
+    let mut term = x;
+    let mut sum = x;
+
+    for n in 1..10 {
+        term *= -x_squared / ((2 * n) * (2 * n + 1)) as f64;
+        sum += term;
+    }
+
+    sum
+}
+
+pub fn cos(value: f64) -> f64 {
+    sin(value + core::f64::consts::FRAC_PI_2)
+}
+
 pub fn clamp_4(value: i32) -> i8 {
     if value > 7 {
         return 7;
@@ -72,7 +107,7 @@ pub fn sample_count_to_byte_count(sample_count: usize) -> usize {
 fn nibble_count_to_sample_count(nibble_count: usize) -> usize {
     let frames = nibble_count / NIBBLES_PER_FRAME;
     let extra_nibbles = nibble_count % NIBBLES_PER_FRAME;
-    let extra_samples = if extra_nibbles < 2 { 0 } else { extra_nibbles - 2 };
+    let extra_samples = extra_nibbles.saturating_sub(2);
 
     SAMPLES_PER_FRAME * frames + extra_samples
 }
@@ -85,8 +120,24 @@ pub fn sample_count_to_nibble_count(sample_count: usize) -> usize {
     NIBBLES_PER_FRAME * frames + extra_nibbles
 }
 
+/// The nibble address of `sample` within a stream, counting the header nibbles of every frame
+/// up to and including the one that holds it.
+pub fn sample_to_nibble_address(sample: usize) -> usize {
+    let frames = sample / SAMPLES_PER_FRAME;
+    let extra_samples = sample % SAMPLES_PER_FRAME;
+
+    NIBBLES_PER_FRAME * frames + extra_samples + 2
+}
+
+pub fn nibble_address_to_sample(address: usize) -> usize {
+    let frames = address / NIBBLES_PER_FRAME;
+    let extra_nibbles = address % NIBBLES_PER_FRAME;
+
+    SAMPLES_PER_FRAME * frames + extra_nibbles.saturating_sub(2)
+}
+
 pub fn get_next_multiple(value: usize, multiple: usize) -> usize {
-    if multiple == 0 || value % multiple == 0 {
+    if multiple == 0 || value.is_multiple_of(multiple) {
         value
     } else {
         value + multiple - value % multiple
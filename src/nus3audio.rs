@@ -0,0 +1,295 @@
+//! `.nus3audio` archives, as used by Super Smash Bros. Ultimate.
+//!
+//! An archive is a `NUS3` header followed by little-endian chunks, each a 4-byte magic and a
+//! `u32` size:
+//!
+//! * `AUDIINDX` (an 8-byte magic): the number of entries.
+//! * `TNID`: a `u32` ID per entry.
+//! * `NMOF`: a `u32` offset per entry to its name.
+//! * `ADOF`: a `u32` offset and `u32` size per entry to its data.
+//! * `TNNM`: the names, null terminated.
+//! * `JUNK`: padding.
+//! * `PACK`: the data of every entry, each padded to 0x10 bytes.
+//!
+//! Offsets are from the start of the file. Entries are usually IDSP files, but can be any
+//! format, so they are kept as bytes here.
+
+use crate::idsp::{read_idsp_bytes, write_idsp_bytes, DecodeError, EncodeError, IdspContainer};
+use alloc::{string::String, vec, vec::Vec};
+use bytes::{Buf, BufMut, BytesMut};
+#[cfg(feature = "std")]
+use std::{fs::File, io::Read, path::Path};
+
+pub(crate) const NUS3_HEADER: &[u8] = b"NUS3";
+pub(crate) const AUDIINDX: &[u8] = b"AUDIINDX";
+const TNID: &[u8] = b"TNID";
+const NMOF: &[u8] = b"NMOF";
+const ADOF: &[u8] = b"ADOF";
+const TNNM: &[u8] = b"TNNM";
+const JUNK: &[u8] = b"JUNK";
+const PACK: &[u8] = b"PACK";
+const CHUNK_HEADER_SIZE: usize = 8;
+const DATA_ALIGNMENT: usize = 0x10;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Nus3Audio {
+    pub entries: Vec<Nus3AudioEntry>,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Nus3AudioEntry {
+    pub id: u32,
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+impl core::fmt::Debug for Nus3AudioEntry {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("Nus3AudioEntry")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .field("len", &self.data.len())
+            .finish()
+    }
+}
+
+impl Nus3Audio {
+    pub fn entry(&self, name: &str) -> Option<&Nus3AudioEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    pub fn entry_mut(&mut self, name: &str) -> Option<&mut Nus3AudioEntry> {
+        self.entries.iter_mut().find(|entry| entry.name == name)
+    }
+
+    /// Replaces the data of the entry called `name`, returning the old data, or `None` if
+    /// there is no such entry.
+    pub fn replace(&mut self, name: &str, data: Vec<u8>) -> Option<Vec<u8>> {
+        self.entry_mut(name).map(|entry| core::mem::replace(&mut entry.data, data))
+    }
+}
+
+impl Nus3AudioEntry {
+    pub fn is_idsp(&self) -> bool {
+        self.data.starts_with(b"IDSP")
+    }
+
+    pub fn read_idsp(&self) -> Result<IdspContainer, DecodeError> {
+        read_idsp_bytes(&self.data)
+    }
+
+    pub fn write_idsp(&mut self, container: &IdspContainer) -> Result<(), EncodeError> {
+        self.data = write_idsp_bytes(container)?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+pub fn read_nus3audio<P: AsRef<Path>>(file_path: P) -> Result<Nus3Audio, DecodeError> {
+    let mut file = File::open(file_path)?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+
+    read_nus3audio_bytes(&bytes)
+}
+
+pub fn read_nus3audio_bytes(original_bytes: &[u8]) -> Result<Nus3Audio, DecodeError> {
+    let audiindx_magic = original_bytes.get(8..8 + AUDIINDX.len());
+    if !original_bytes.starts_with(NUS3_HEADER) || audiindx_magic != Some(AUDIINDX) {
+        return Err(DecodeError::InvalidHeader);
+    }
+
+    let mut ids = None;
+    let mut name_offsets = None;
+    let mut data_ranges = None;
+
+    // AUDIINDX is the only chunk with an 8-byte magic, so start with the chunk after it.
+    let mut bytes = &original_bytes[8 + AUDIINDX.len()..];
+    let audiindx = read_chunk_body(&mut bytes)?;
+    let entry_count = audiindx.get(..4).ok_or(DecodeError::InvalidHeader)?.get_u32_le() as usize;
+
+    while bytes.len() >= CHUNK_HEADER_SIZE {
+        let mut magic = [0; 4];
+        bytes.copy_to_slice(&mut magic);
+        let mut body = read_chunk_body(&mut bytes)?;
+
+        match &magic[..] {
+            TNID => ids = Some(read_u32s(&mut body, entry_count)?),
+            NMOF => name_offsets = Some(read_u32s(&mut body, entry_count)?),
+            ADOF => data_ranges = Some(read_u32s(&mut body, entry_count * 2)?),
+            PACK => break,
+            _ => {},
+        }
+    }
+
+    let (ids, name_offsets, data_ranges) = match (ids, name_offsets, data_ranges) {
+        (Some(ids), Some(name_offsets), Some(data_ranges)) => (ids, name_offsets, data_ranges),
+        _ => return Err(DecodeError::InvalidHeader),
+    };
+
+    let mut entries = vec![];
+    for i in 0..entry_count {
+        let name_bytes = original_bytes.get(name_offsets[i]..).ok_or(DecodeError::InvalidHeader)?;
+        let name_len = name_bytes.iter().position(|&b| b == 0).ok_or(DecodeError::InvalidHeader)?;
+        let name = String::from_utf8(name_bytes[..name_len].to_vec())
+            .map_err(|_| DecodeError::InvalidHeader)?;
+
+        let (offset, size) = (data_ranges[i * 2], data_ranges[i * 2 + 1]);
+        let data =
+            original_bytes.get(offset..offset + size).ok_or(DecodeError::InvalidAudioLength)?;
+
+        entries.push(Nus3AudioEntry { id: ids[i] as u32, name, data: data.to_vec() });
+    }
+
+    Ok(Nus3Audio { entries })
+}
+
+fn read_chunk_body<'a>(bytes: &mut &'a [u8]) -> Result<&'a [u8], DecodeError> {
+    if bytes.len() < 4 {
+        return Err(DecodeError::InvalidHeader);
+    }
+
+    let size = bytes.get_u32_le() as usize;
+    let body = bytes.get(..size).ok_or(DecodeError::InvalidHeader)?;
+    bytes.advance(size);
+
+    Ok(body)
+}
+
+fn read_u32s(body: &mut &[u8], count: usize) -> Result<Vec<usize>, DecodeError> {
+    if body.len() < count * 4 {
+        return Err(DecodeError::InvalidHeader);
+    }
+
+    Ok((0..count).map(|_| body.get_u32_le() as usize).collect())
+}
+
+/// Writes `archive` out, laying the names and data out again from scratch.
+pub fn write_nus3audio_bytes(archive: &Nus3Audio) -> Vec<u8> {
+    let entry_count = archive.entries.len();
+    let names_len: usize = archive.entries.iter().map(|entry| entry.name.len() + 1).sum();
+
+    let names_start = NUS3_HEADER.len()
+        + 4
+        + AUDIINDX.len()
+        + 8
+        + 2 * (CHUNK_HEADER_SIZE + 4 * entry_count)
+        + CHUNK_HEADER_SIZE
+        + 8 * entry_count
+        + CHUNK_HEADER_SIZE;
+    let junk_start = names_start + names_len;
+    let junk_len = padding(junk_start + 2 * CHUNK_HEADER_SIZE);
+    let pack_start = junk_start + 2 * CHUNK_HEADER_SIZE + junk_len;
+    let pack_len: usize =
+        archive.entries.iter().map(|entry| entry.data.len() + padding(entry.data.len())).sum();
+
+    let mut bytes = BytesMut::new();
+    bytes.extend_from_slice(NUS3_HEADER);
+    bytes.put_u32_le((pack_start + pack_len - 8) as u32);
+
+    bytes.extend_from_slice(AUDIINDX);
+    bytes.put_u32_le(4);
+    bytes.put_u32_le(entry_count as u32);
+
+    bytes.extend_from_slice(TNID);
+    bytes.put_u32_le((entry_count * 4) as u32);
+    for entry in &archive.entries {
+        bytes.put_u32_le(entry.id);
+    }
+
+    bytes.extend_from_slice(NMOF);
+    bytes.put_u32_le((entry_count * 4) as u32);
+    let mut name_offset = names_start;
+    for entry in &archive.entries {
+        bytes.put_u32_le(name_offset as u32);
+        name_offset += entry.name.len() + 1;
+    }
+
+    bytes.extend_from_slice(ADOF);
+    bytes.put_u32_le((entry_count * 8) as u32);
+    let mut data_offset = pack_start;
+    for entry in &archive.entries {
+        bytes.put_u32_le(data_offset as u32);
+        bytes.put_u32_le(entry.data.len() as u32);
+        data_offset += entry.data.len() + padding(entry.data.len());
+    }
+
+    bytes.extend_from_slice(TNNM);
+    bytes.put_u32_le(names_len as u32);
+    for entry in &archive.entries {
+        bytes.extend_from_slice(entry.name.as_bytes());
+        bytes.put_u8(0);
+    }
+
+    bytes.extend_from_slice(JUNK);
+    bytes.put_u32_le(junk_len as u32);
+    bytes.resize(bytes.len() + junk_len, 0);
+
+    bytes.extend_from_slice(PACK);
+    bytes.put_u32_le(pack_len as u32);
+    for entry in &archive.entries {
+        bytes.extend_from_slice(&entry.data);
+        bytes.resize(bytes.len() + padding(entry.data.len()), 0);
+    }
+
+    bytes.to_vec()
+}
+
+fn padding(len: usize) -> usize {
+    (DATA_ALIGNMENT - len % DATA_ALIGNMENT) % DATA_ALIGNMENT
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::idsp::BlockPadding;
+
+    fn archive() -> Nus3Audio {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+
+        Nus3Audio {
+            entries: vec![
+                Nus3AudioEntry { id: 0, name: "se_common_13".into(), data: idsp_bytes.to_vec() },
+                Nus3AudioEntry { id: 1, name: "raw".into(), data: vec![1, 2, 3] },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_nus3audio_roundtrip() {
+        let archive = archive();
+        let bytes = write_nus3audio_bytes(&archive);
+
+        assert_eq!(&bytes[4..8], &((bytes.len() - 8) as u32).to_le_bytes());
+        assert_eq!(read_nus3audio_bytes(&bytes).unwrap(), archive);
+
+        let entry = archive.entry("se_common_13").unwrap();
+        assert!(entry.is_idsp());
+        assert!(!archive.entry("raw").unwrap().is_idsp());
+        assert_eq!(entry.read_idsp().unwrap().sample_count, 47035);
+    }
+
+    #[test]
+    fn test_nus3audio_replace() {
+        let mut archive = archive();
+        let entry = archive.entry_mut("se_common_13").unwrap();
+        let mut container = entry.read_idsp().unwrap();
+        container.reblock(0x8000, BlockPadding::FullBlock).unwrap();
+        entry.write_idsp(&container).unwrap();
+
+        assert_eq!(archive.replace("raw", vec![4; 0x21]), Some(vec![1, 2, 3]));
+        assert_eq!(archive.replace("missing", vec![]), None);
+
+        let bytes = write_nus3audio_bytes(&archive);
+        let read = read_nus3audio_bytes(&bytes).unwrap();
+        assert_eq!(read, archive);
+        assert_eq!(read.entries[0].read_idsp().unwrap().interleave_size, 0x8000);
+
+        // Entries stay aligned after growing.
+        let mut adof = &bytes[bytes.windows(4).position(|w| w == ADOF).unwrap() + 8..];
+        for _ in 0..2 {
+            assert_eq!(adof.get_u32_le() as usize % DATA_ALIGNMENT, 0);
+            adof.advance(4);
+        }
+    }
+}
@@ -0,0 +1,419 @@
+//! `.nus3bank` sound banks, as used by Super Smash Bros. for Wii U and 3DS.
+//!
+//! A bank starts with `NUS3`, the `u32` size of the rest of the file, and a `BANKTOC ` table
+//! listing the magic and size of each chunk that follows (`PROP`, `BINF`, `GRP `, `DTON`,
+//! `TONE`, `JUNK`, `PACK`...). Every chunk is its magic, a `u32` size and its body. All
+//! numbers are little endian.
+//!
+//! The `TONE` body is a `u32` count followed by an `(offset, size)` pair per tone, with
+//! offsets from the start of the body. Records of 0xC bytes or less are placeholders.
+//! Otherwise a record holds:
+//!
+//! | Offset | Field                                                          |
+//! |--------|----------------------------------------------------------------|
+//! | 0x02   | Flags, with bit 1 meaning 4 extra bytes follow the fixed header |
+//! | 0x0C   | Name length including the null terminator, then the name, padded to 4 bytes |
+//! | +0x04  | Offset of the stream from the start of the `PACK` body        |
+//! | +0x08  | Size of the stream                                             |
+//!
+//! Streams are usually IDSP files. Since most of the bank isn't understood, it is kept as
+//! bytes and only the parts that depend on a replaced stream are rewritten.
+
+use crate::idsp::{read_idsp_bytes, write_idsp_bytes, DecodeError, EncodeError, IdspContainer};
+use alloc::{string::String, vec, vec::Vec};
+use bytes::Buf;
+#[cfg(feature = "std")]
+use std::{fs::File, io::Read, path::Path};
+
+const NUS3_HEADER: &[u8] = b"NUS3";
+pub(crate) const BANKTOC: &[u8] = b"BANKTOC ";
+const TONE: &[u8] = b"TONE";
+const PACK: &[u8] = b"PACK";
+const TOC_OFFSET: usize = 0x10;
+const CHUNK_HEADER_SIZE: usize = 8;
+const PLACEHOLDER_TONE_SIZE: usize = 0xC;
+const TONE_FLAG_EXTRA_HEADER: u8 = 0x02;
+const STREAM_ALIGNMENT: usize = 0x10;
+
+#[derive(Clone, PartialEq)]
+pub struct Nus3Bank {
+    bytes: Vec<u8>,
+    tones: Vec<Tone>,
+    /// Offsets of the `PACK` size in the table of contents and in the chunk header.
+    pack_size_offsets: [usize; 2],
+    pack_start: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tone {
+    /// The position of the tone in the `TONE` chunk, counting placeholders.
+    pub index: usize,
+    pub name: String,
+    /// Offset of the stream from the start of the file.
+    pub offset: usize,
+    pub size: usize,
+    /// Offset of the stream offset and size in the tone record.
+    stream_info_offset: usize,
+}
+
+impl core::fmt::Debug for Nus3Bank {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("Nus3Bank").field("tones", &self.tones).finish()
+    }
+}
+
+impl Nus3Bank {
+    /// Every tone with a stream, in the order they appear in the bank.
+    pub fn tones(&self) -> &[Tone] {
+        &self.tones
+    }
+
+    pub fn tone(&self, name: &str) -> Option<&Tone> {
+        self.tones.iter().find(|tone| tone.name == name)
+    }
+
+    pub fn tone_data(&self, tone: &Tone) -> &[u8] {
+        &self.bytes[tone.offset..tone.offset + tone.size]
+    }
+
+    pub fn read_idsp(&self, tone: &Tone) -> Result<IdspContainer, DecodeError> {
+        read_idsp_bytes(self.tone_data(tone))
+    }
+
+    /// Replaces the stream of the tone at `tone_index` in [`tones`](Self::tones). Streams after
+    /// it move by a multiple of 0x10 bytes to stay aligned, and their offsets are updated
+    /// along with the sizes of the stream, the `PACK` chunk and the file.
+    pub fn replace_tone(&mut self, tone_index: usize, data: &[u8]) -> Result<(), EncodeError> {
+        let tone = self.tones.get(tone_index).ok_or(EncodeError::InvalidIndex)?.clone();
+        let padding =
+            (STREAM_ALIGNMENT - data.len() % STREAM_ALIGNMENT + tone.size) % STREAM_ALIGNMENT;
+
+        let mut replacement = data.to_vec();
+        replacement.resize(data.len() + padding, 0);
+        let new_len = replacement.len();
+        self.bytes.splice(tone.offset..tone.offset + tone.size, replacement);
+
+        let grow = |value: usize| value + new_len - tone.size;
+
+        for other in &mut self.tones {
+            if other.offset == tone.offset {
+                // Tones can share a stream.
+                other.size = data.len();
+            } else if other.offset > tone.offset {
+                other.offset = grow(other.offset);
+            }
+
+            let stream_offset = other.offset - self.pack_start;
+            write_u32(&mut self.bytes, other.stream_info_offset, stream_offset);
+            write_u32(&mut self.bytes, other.stream_info_offset + 4, other.size);
+        }
+
+        for &offset in &self.pack_size_offsets {
+            let size = read_u32(&self.bytes, offset);
+            write_u32(&mut self.bytes, offset, grow(size));
+        }
+
+        let file_size = self.bytes.len() - CHUNK_HEADER_SIZE;
+        write_u32(&mut self.bytes, NUS3_HEADER.len(), file_size);
+
+        Ok(())
+    }
+
+    pub fn replace_tone_idsp(
+        &mut self,
+        tone_index: usize,
+        container: &IdspContainer,
+    ) -> Result<(), EncodeError> {
+        self.replace_tone(tone_index, &write_idsp_bytes(container)?)
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> usize {
+    (&bytes[offset..]).get_u32_le() as usize
+}
+
+fn write_u32(bytes: &mut [u8], offset: usize, value: usize) {
+    bytes[offset..offset + 4].copy_from_slice(&(value as u32).to_le_bytes());
+}
+
+#[cfg(feature = "std")]
+pub fn read_nus3bank<P: AsRef<Path>>(file_path: P) -> Result<Nus3Bank, DecodeError> {
+    let mut file = File::open(file_path)?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+
+    read_nus3bank_bytes(&bytes)
+}
+
+pub fn read_nus3bank_bytes(original_bytes: &[u8]) -> Result<Nus3Bank, DecodeError> {
+    let toc_magic = original_bytes.get(CHUNK_HEADER_SIZE..TOC_OFFSET);
+    if !original_bytes.starts_with(NUS3_HEADER) || toc_magic != Some(BANKTOC) {
+        return Err(DecodeError::InvalidHeader);
+    }
+
+    let mut toc = original_bytes.get(TOC_OFFSET..).ok_or(DecodeError::InvalidHeader)?;
+    if toc.len() < 8 {
+        return Err(DecodeError::InvalidHeader);
+    }
+
+    let toc_size = toc.get_u32_le() as usize;
+    let chunk_count = toc.get_u32_le() as usize;
+    if toc.len() < chunk_count * CHUNK_HEADER_SIZE {
+        return Err(DecodeError::InvalidHeader);
+    }
+
+    let mut tone_chunk = None;
+    let mut pack_chunk = None;
+    let mut chunk_offset = TOC_OFFSET + 4 + toc_size;
+
+    for i in 0..chunk_count {
+        let toc_entry = TOC_OFFSET + 8 + i * CHUNK_HEADER_SIZE;
+        let magic = &original_bytes[toc_entry..toc_entry + 4];
+        let size = read_u32(original_bytes, toc_entry + 4);
+        let body = chunk_offset + CHUNK_HEADER_SIZE;
+
+        if original_bytes.len() < body + size {
+            return Err(DecodeError::InvalidHeader);
+        }
+
+        match magic {
+            TONE => tone_chunk = Some((body, size)),
+            PACK => pack_chunk = Some((toc_entry + 4, body, size)),
+            _ => {},
+        }
+
+        chunk_offset = body + size;
+    }
+
+    let ((tone_start, tone_size), (pack_toc_size_offset, pack_start, pack_size)) =
+        match (tone_chunk, pack_chunk) {
+            (Some(tone), Some(pack)) => (tone, pack),
+            _ => return Err(DecodeError::InvalidHeader),
+        };
+
+    let tone_body = &original_bytes[tone_start..tone_start + tone_size];
+    let tone_count = tone_body.get(..4).ok_or(DecodeError::InvalidHeader)?.get_u32_le() as usize;
+    let mut tones = vec![];
+
+    for index in 0..tone_count {
+        let entry = 4 + index * 8;
+        if tone_body.len() < entry + 8 {
+            return Err(DecodeError::InvalidHeader);
+        }
+
+        let record_offset = read_u32(tone_body, entry);
+        let record_size = read_u32(tone_body, entry + 4);
+        if record_size <= PLACEHOLDER_TONE_SIZE {
+            continue;
+        }
+
+        let record = tone_body
+            .get(record_offset..record_offset + record_size)
+            .ok_or(DecodeError::InvalidHeader)?;
+        let mut name_offset = PLACEHOLDER_TONE_SIZE;
+        if record[2] & TONE_FLAG_EXTRA_HEADER != 0 {
+            name_offset += 4;
+        }
+
+        let name_size = *record.get(name_offset).ok_or(DecodeError::InvalidHeader)? as usize;
+        let name_bytes = record
+            .get(name_offset + 1..name_offset + 1 + name_size)
+            .ok_or(DecodeError::InvalidHeader)?;
+        let name_len = name_bytes.iter().position(|&b| b == 0).unwrap_or(name_bytes.len());
+        let name = String::from_utf8(name_bytes[..name_len].to_vec())
+            .map_err(|_| DecodeError::InvalidHeader)?;
+
+        let name_end = name_offset + 1 + name_size;
+        let stream_info = name_end + (4 - name_end % 4) % 4 + 4;
+        if record.len() < stream_info + 8 {
+            return Err(DecodeError::InvalidHeader);
+        }
+
+        let offset = pack_start + read_u32(record, stream_info);
+        let size = read_u32(record, stream_info + 4);
+        if offset + size > pack_start + pack_size {
+            return Err(DecodeError::InvalidAudioLength);
+        }
+
+        tones.push(Tone {
+            index,
+            name,
+            offset,
+            size,
+            stream_info_offset: tone_start + record_offset + stream_info,
+        });
+    }
+
+    Ok(Nus3Bank {
+        bytes: original_bytes.to_vec(),
+        tones,
+        pack_size_offsets: [pack_toc_size_offset, pack_start - 4],
+        pack_start,
+    })
+}
+
+pub fn write_nus3bank_bytes(bank: &Nus3Bank) -> Vec<u8> {
+    bank.bytes.clone()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::idsp::BlockPadding;
+
+    fn chunk(magic: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut bytes = magic.to_vec();
+        bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    fn tone_record(flags: u8, name: &str, offset: usize, size: usize) -> Vec<u8> {
+        let mut record = vec![0, 0, flags, 0, 0xAA, 0xBB, 0xCC, 0xDD, 0, 0, 0, 0];
+        if flags & TONE_FLAG_EXTRA_HEADER != 0 {
+            record.extend_from_slice(&[0xEE; 4]);
+        }
+        record.push(name.len() as u8 + 1);
+        record.extend_from_slice(name.as_bytes());
+        record.push(0);
+        record.resize(record.len().div_ceil(4) * 4, 0);
+        record.extend_from_slice(&[0x11; 4]);
+        record.extend_from_slice(&(offset as u32).to_le_bytes());
+        record.extend_from_slice(&(size as u32).to_le_bytes());
+        record.extend_from_slice(&[0x22; 8]);
+        record
+    }
+
+    /// A bank with a placeholder tone, an IDSP stream and a second, raw stream.
+    fn bank_bytes() -> Vec<u8> {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let idsp_len = idsp_bytes.len();
+        let idsp_slot = idsp_len.div_ceil(0x10) * 0x10;
+
+        let records = [
+            vec![0; 8],
+            tone_record(0, "bgm_13", 0, idsp_len),
+            tone_record(TONE_FLAG_EXTRA_HEADER, "se_raw", idsp_slot, 5),
+        ];
+        let mut tone = (records.len() as u32).to_le_bytes().to_vec();
+        let mut record_offset = 4 + records.len() * 8;
+        for record in &records {
+            tone.extend_from_slice(&(record_offset as u32).to_le_bytes());
+            tone.extend_from_slice(&(record.len() as u32).to_le_bytes());
+            record_offset += record.len();
+        }
+        for record in &records {
+            tone.extend_from_slice(record);
+        }
+
+        let mut pack = idsp_bytes.to_vec();
+        pack.resize(idsp_slot, 0);
+        pack.extend_from_slice(&[1, 2, 3, 4, 5]);
+
+        let chunks = [
+            (&b"PROP"[..], vec![0x33; 12]),
+            (TONE, tone),
+            (&b"JUNK"[..], vec![0; 4]),
+            (PACK, pack),
+        ];
+        let mut toc = (chunks.len() as u32).to_le_bytes().to_vec();
+        let mut body = vec![];
+        for (magic, chunk_body) in &chunks {
+            toc.extend_from_slice(magic);
+            toc.extend_from_slice(&(chunk_body.len() as u32).to_le_bytes());
+            body.extend_from_slice(&chunk(magic, chunk_body));
+        }
+
+        let mut rest = chunk(BANKTOC, &toc);
+        rest.extend_from_slice(&body);
+        chunk(NUS3_HEADER, &rest)
+    }
+
+    #[test]
+    fn test_nus3bank_read() {
+        let bytes = bank_bytes();
+        let bank = read_nus3bank_bytes(&bytes).unwrap();
+
+        let names: Vec<_> =
+            bank.tones().iter().map(|tone| (tone.index, tone.name.as_str())).collect();
+        assert_eq!(names, vec![(1, "bgm_13"), (2, "se_raw")]);
+
+        let tone = bank.tone("bgm_13").unwrap();
+        assert_eq!(bank.read_idsp(tone).unwrap().sample_count, 47035);
+        assert_eq!(bank.tone_data(bank.tone("se_raw").unwrap()), &[1, 2, 3, 4, 5]);
+        assert_eq!(write_nus3bank_bytes(&bank), bytes);
+    }
+
+    #[test]
+    fn test_nus3bank_layout() {
+        // A minimal bank written out by hand, with one tone named "bgm" and an 8 byte stream.
+        #[rustfmt::skip]
+        let bytes = [
+            b'N', b'U', b'S', b'3', 0x6C, 0, 0, 0,
+            b'B', b'A', b'N', b'K', b'T', b'O', b'C', b' ', 0x14, 0, 0, 0, 2, 0, 0, 0,
+            b'T', b'O', b'N', b'E', 0x2C, 0, 0, 0,
+            b'P', b'A', b'C', b'K', 0x10, 0, 0, 0,
+            // 0x28: TONE, with a count and one (offset, size) pair.
+            b'T', b'O', b'N', b'E', 0x2C, 0, 0, 0, 1, 0, 0, 0, 0x0C, 0, 0, 0, 0x20, 0, 0, 0,
+            // 0x3C: the tone record.
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            4, b'b', b'g', b'm', 0, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 0, 0, 8, 0, 0, 0,
+            // 0x5C: PACK, with the stream and its padding.
+            b'P', b'A', b'C', b'K', 0x10, 0, 0, 0,
+            1, 2, 3, 4, 5, 6, 7, 8, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+
+        let mut bank = read_nus3bank_bytes(&bytes).unwrap();
+        let tone = &bank.tones()[0];
+        assert_eq!((tone.index, tone.name.as_str(), tone.offset, tone.size), (0, "bgm", 0x64, 8));
+        assert_eq!(bank.tone_data(tone), &[1, 2, 3, 4, 5, 6, 7, 8]);
+
+        assert!(matches!(bank.replace_tone(1, &[0; 12]), Err(EncodeError::InvalidIndex)));
+        bank.replace_tone(0, &[9; 12]).unwrap();
+
+        // The sizes of the file and PACK grow by a whole 0x10, and the tone's size changes.
+        let mut expected = bytes.to_vec();
+        expected[0x04] = 0x7C;
+        expected[0x24] = 0x20;
+        expected[0x58] = 12;
+        expected[0x60] = 0x20;
+        expected.splice(0x64..0x6C, [9; 12].iter().chain(&[0; 12]).copied());
+        assert_eq!(write_nus3bank_bytes(&bank), expected);
+    }
+
+    #[test]
+    fn test_nus3bank_replace() {
+        let bytes = bank_bytes();
+        let mut bank = read_nus3bank_bytes(&bytes).unwrap();
+        let original_raw_offset = bank.tones()[1].offset;
+
+        let mut container = bank.read_idsp(&bank.tones()[0]).unwrap();
+        container.reblock(0x8000, BlockPadding::FullBlock).unwrap();
+        bank.replace_tone_idsp(0, &container).unwrap();
+
+        let replaced = write_nus3bank_bytes(&bank);
+        let reread = read_nus3bank_bytes(&replaced).unwrap();
+        assert_eq!(reread, bank);
+
+        let (idsp, raw) = (&reread.tones()[0], &reread.tones()[1]);
+        assert_eq!(reread.read_idsp(idsp).unwrap().interleave_size, 0x8000);
+        assert_eq!(reread.tone_data(raw), &[1, 2, 3, 4, 5]);
+        assert_eq!((raw.offset - reread.pack_start) % STREAM_ALIGNMENT, 0);
+
+        // Everything up to the first stream is unchanged apart from the sizes and offsets.
+        let [pack_toc_size, pack_size] = bank.pack_size_offsets;
+        let changed =
+            [4, pack_toc_size, pack_size, raw.stream_info_offset, idsp.stream_info_offset + 4];
+        let header_len = bank.tones()[0].offset;
+        for i in 0..header_len {
+            if !changed.iter().any(|&offset| (offset..offset + 4).contains(&i)) {
+                assert_eq!(replaced[i], bytes[i], "byte {:#x} changed", i);
+            }
+        }
+
+        let shift = raw.offset - original_raw_offset;
+        assert_eq!(replaced[raw.offset..], bytes[original_raw_offset..]);
+        assert_eq!(replaced.len(), bytes.len() + shift);
+    }
+}
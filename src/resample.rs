@@ -0,0 +1,160 @@
+use crate::math::{clamp_16, cos, round, sin};
+use alloc::vec::Vec;
+use core::f64::consts::PI;
+
+/// Zero crossings of the sinc kernel on each side of a sample, at the lower of the two rates.
+const SINC_ZERO_CROSSINGS: f64 = 16.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResampleQuality {
+    /// Interpolates between the two nearest samples. Fast, but aliases when downsampling.
+    Linear,
+    /// Blackman-windowed sinc, low-passed below the lower of the two Nyquist frequencies.
+    #[default]
+    WindowedSinc,
+}
+
+/// Maps a sample position at `from_rate` to the nearest one at `to_rate`. Loop points and
+/// lengths are scaled with this so they stay consistent with [`resample`].
+pub fn scale_position(position: usize, from_rate: usize, to_rate: usize) -> usize {
+    round(position as f64 * to_rate as f64 / from_rate as f64) as usize
+}
+
+/// Converts `pcm` from `from_rate` to `to_rate`, returning
+/// `scale_position(pcm.len(), from_rate, to_rate)` samples.
+///
+/// Panics if either rate is zero.
+pub fn resample(
+    pcm: &[i16],
+    from_rate: usize,
+    to_rate: usize,
+    quality: ResampleQuality,
+) -> Vec<i16> {
+    assert!(from_rate > 0 && to_rate > 0, "can't resample from {} to {}", from_rate, to_rate);
+
+    if from_rate == to_rate || pcm.is_empty() {
+        return pcm.to_vec();
+    }
+
+    let step = from_rate as f64 / to_rate as f64;
+    let output_len = scale_position(pcm.len(), from_rate, to_rate);
+
+    match quality {
+        ResampleQuality::Linear => (0..output_len)
+            .map(|n| {
+                let position = n as f64 * step;
+                let index = position as usize;
+                let fraction = position - index as f64;
+                let current = pcm[index.min(pcm.len() - 1)] as f64;
+                let next = pcm[(index + 1).min(pcm.len() - 1)] as f64;
+
+                clamp_16(round(current + (next - current) * fraction) as i32)
+            })
+            .collect(),
+        ResampleQuality::WindowedSinc => {
+            // The cutoff relative to the input's Nyquist frequency, which widens the kernel
+            // when downsampling.
+            let cutoff = (to_rate as f64 / from_rate as f64).min(1.0);
+            let half_width = SINC_ZERO_CROSSINGS / cutoff;
+
+            (0..output_len)
+                .map(|n| {
+                    let position = n as f64 * step;
+                    let first = (position - half_width).max(0.0) as usize;
+                    let last = ((position + half_width) as usize).min(pcm.len() - 1);
+                    let mut sum = 0.0;
+                    let mut weight_sum = 0.0;
+
+                    for (k, &sample) in pcm.iter().enumerate().take(last + 1).skip(first) {
+                        let distance = position - k as f64;
+                        let weight = sinc(distance * cutoff) * blackman(distance / half_width);
+                        sum += sample as f64 * weight;
+                        weight_sum += weight;
+                    }
+
+                    // Normalizing keeps unity gain, including at the edges where the kernel
+                    // is cut off.
+                    let value = if weight_sum == 0.0 { 0.0 } else { sum / weight_sum };
+                    clamp_16(round(value) as i32)
+                })
+                .collect()
+        },
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        sin(PI * x) / (PI * x)
+    }
+}
+
+/// The Blackman window over `-1.0..=1.0`.
+fn blackman(x: f64) -> f64 {
+    if !(-1.0..=1.0).contains(&x) {
+        return 0.0;
+    }
+
+    0.42 + 0.5 * cos(PI * x) + 0.08 * cos(2.0 * PI * x)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sine(frequency: f64, sample_rate: usize, len: usize) -> Vec<i16> {
+        (0..len)
+            .map(|n| {
+                (10000.0 * (2.0 * PI * frequency * n as f64 / sample_rate as f64).sin()) as i16
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sin() {
+        for i in -100..100 {
+            let x = i as f64 * 0.37;
+            assert!((sin(x) - x.sin()).abs() < 1e-7);
+            assert!((cos(x) - x.cos()).abs() < 1e-7);
+        }
+    }
+
+    #[test]
+    fn test_resample_sine() {
+        let input = sine(440.0, 48000, 4800);
+        let expected = sine(440.0, 32000, 3200);
+
+        for &(quality, tolerance) in
+            &[(ResampleQuality::Linear, 40), (ResampleQuality::WindowedSinc, 20)]
+        {
+            let output = resample(&input, 48000, 32000, quality);
+            assert_eq!(output.len(), 3200);
+
+            // Skip the edges, where the kernel runs out of input.
+            let error = output[100..3100]
+                .iter()
+                .zip(&expected[100..3100])
+                .map(|(a, b)| (a - b).abs())
+                .max()
+                .unwrap();
+            assert!(error <= tolerance, "{:?} is off by {}", quality, error);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_zero_rate() {
+        resample(&[1, 2, 3], 0, 32000, ResampleQuality::Linear);
+    }
+
+    #[test]
+    fn test_sinc_removes_aliasing() {
+        // 20 kHz is above the 16 kHz Nyquist frequency at 32 kHz, so it should vanish.
+        let input = sine(20000.0, 48000, 4800);
+        let output = resample(&input, 48000, 32000, ResampleQuality::WindowedSinc);
+        let peak = output[100..3100].iter().map(|sample| sample.abs()).max().unwrap();
+
+        assert!(peak < 500, "aliased peak of {}", peak);
+    }
+}
@@ -0,0 +1,178 @@
+//! The 4-bit Yamaha ADPCM played by the Wii Remote speaker.
+//!
+//! Unlike GC-ADPCM there are no frames or coefficients: each nibble scales an adaptive step
+//! size, which is carried from one sample to the next along with the last sample. Bytes hold
+//! two samples, high nibble first. PCM from [`crate::decode::decode_gc_adpcm`] can be encoded
+//! directly, usually after resampling to the few kHz the speaker plays at.
+
+use crate::math::clamp_16;
+use alloc::{vec, vec::Vec};
+
+const STEP_SCALE: [i32; 16] =
+    [230, 230, 230, 230, 307, 409, 512, 614, 230, 230, 230, 230, 307, 409, 512, 614];
+const DIFF_LOOKUP: [i32; 16] = [1, 3, 5, 7, 9, 11, 13, 15, -1, -3, -5, -7, -9, -11, -13, -15];
+const MIN_STEP: i32 = 127;
+const MAX_STEP: i32 = 24576;
+
+/// The decoder state between two samples, so a stream can be encoded or decoded in chunks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct YamahaContext {
+    /// The last sample.
+    pub predictor: i16,
+    /// The adaptive step size each nibble is scaled by, between 127 and 24576.
+    pub step: i16,
+}
+
+impl Default for YamahaContext {
+    fn default() -> Self {
+        Self { predictor: 0, step: MIN_STEP as i16 }
+    }
+}
+
+impl YamahaContext {
+    fn expand_nibble(&mut self, nibble: u8) -> i16 {
+        let step = self.step as i32;
+        let predictor = self.predictor as i32 + step * DIFF_LOOKUP[nibble as usize] / 8;
+
+        self.predictor = clamp_16(predictor);
+        self.step = ((step * STEP_SCALE[nibble as usize]) >> 8).clamp(MIN_STEP, MAX_STEP) as i16;
+        self.predictor
+    }
+
+    fn compress_sample(&mut self, sample: i16) -> u8 {
+        let delta = sample as i32 - self.predictor as i32;
+        let magnitude = (delta.abs() * 4 / self.step as i32).min(7) as u8;
+        let nibble = if delta < 0 { magnitude + 8 } else { magnitude };
+
+        self.expand_nibble(nibble);
+        nibble
+    }
+}
+
+/// Decodes a whole stream from the default context into `2 * adpcm.len()` samples.
+///
+/// That count includes the zero padding nibble at the end of a stream encoded from an odd
+/// number of samples, which decodes to one extra sample.
+pub fn decode_yamaha_adpcm(adpcm: &[u8]) -> Vec<i16> {
+    let mut pcm = vec![0; adpcm.len() * 2];
+    decode_yamaha_adpcm_into(adpcm, &mut YamahaContext::default(), &mut pcm);
+
+    pcm
+}
+
+/// Decodes `adpcm` into `pcm` without allocating, returning the number of samples written.
+///
+/// Decoding stops when either buffer runs out, and `context` is left after the last sample
+/// written, so a stream can be decoded in chunks as long as `pcm` holds an even number of
+/// samples.
+pub fn decode_yamaha_adpcm_into(
+    adpcm: &[u8],
+    context: &mut YamahaContext,
+    pcm: &mut [i16],
+) -> usize {
+    let sample_count = (adpcm.len() * 2).min(pcm.len());
+
+    for (i, out) in pcm[..sample_count].iter_mut().enumerate() {
+        let byte = adpcm[i / 2];
+        let nibble = if i % 2 == 0 { byte >> 4 } else { byte & 0xF };
+        *out = context.expand_nibble(nibble);
+    }
+
+    sample_count
+}
+
+/// Encodes a whole stream from the default context into `pcm.len().div_ceil(2)` bytes,
+/// padding an odd sample count with a zero nibble.
+pub fn encode_yamaha_adpcm(pcm: &[i16]) -> Vec<u8> {
+    let mut adpcm = vec![0; pcm.len().div_ceil(2)];
+    encode_yamaha_adpcm_into(pcm, &mut YamahaContext::default(), &mut adpcm);
+
+    adpcm
+}
+
+/// Encodes `pcm` into `adpcm` without allocating, returning the number of bytes written.
+///
+/// Encoding stops when either buffer runs out. An odd sample count leaves the low nibble of
+/// the last byte zeroed, without it affecting `context`, so every chunk but the last has to
+/// hold an even number of samples.
+pub fn encode_yamaha_adpcm_into(
+    pcm: &[i16],
+    context: &mut YamahaContext,
+    adpcm: &mut [u8],
+) -> usize {
+    let sample_count = pcm.len().min(adpcm.len() * 2);
+
+    for (out, samples) in adpcm.iter_mut().zip(pcm[..sample_count].chunks(2)) {
+        let high = context.compress_sample(samples[0]);
+        let low = samples.get(1).map_or(0, |&sample| context.compress_sample(sample));
+        *out = (high << 4) | low;
+    }
+
+    sample_count.div_ceil(2)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        decode::decode_gc_adpcm,
+        idsp::read_idsp_bytes,
+        resample::{resample, ResampleQuality},
+    };
+    use core::f64::consts::PI;
+
+    #[test]
+    fn test_sine_roundtrip() {
+        let pcm: Vec<i16> = (0..6000)
+            .map(|n| (8000.0 * (2.0 * PI * 200.0 * n as f64 / 6000.0).sin()) as i16)
+            .collect();
+
+        let adpcm = encode_yamaha_adpcm(&pcm);
+        assert_eq!(adpcm.len(), 3000);
+
+        let decoded = decode_yamaha_adpcm(&adpcm);
+        // Skip the first cycle, where the step size is still adapting.
+        let error = decoded[30..]
+            .iter()
+            .zip(&pcm[30..])
+            .map(|(&a, &b)| (a as i32 - b as i32).abs())
+            .max()
+            .unwrap();
+        assert!(error < 600, "off by {}", error);
+    }
+
+    #[test]
+    fn test_streaming() {
+        let pcm: Vec<i16> = (0..1001).map(|n| ((n * 37) % 2000 - 1000) as i16).collect();
+        let expected = encode_yamaha_adpcm(&pcm);
+
+        let mut context = YamahaContext::default();
+        let mut adpcm = vec![0; expected.len()];
+        let written = encode_yamaha_adpcm_into(&pcm[..400], &mut context, &mut adpcm[..200]);
+        assert_eq!(written, 200);
+        encode_yamaha_adpcm_into(&pcm[400..], &mut context, &mut adpcm[200..]);
+        assert_eq!(adpcm, expected);
+
+        let mut context = YamahaContext::default();
+        let mut decoded = vec![0; pcm.len()];
+        assert_eq!(decode_yamaha_adpcm_into(&adpcm[..100], &mut context, &mut decoded[..200]), 200);
+        decode_yamaha_adpcm_into(&adpcm[100..], &mut context, &mut decoded[200..]);
+        assert_eq!(decoded[..], decode_yamaha_adpcm(&adpcm)[..pcm.len()]);
+    }
+
+    #[test]
+    fn test_from_gc_adpcm() {
+        let idsp_bytes = include_bytes!("../test_files/13.idsp");
+        let idsp_file = read_idsp_bytes(idsp_bytes).unwrap();
+        let channel = &idsp_file.channels[0];
+
+        let pcm = decode_gc_adpcm(&channel.audio, &channel.metadata.coefficients);
+        let pcm =
+            resample(&pcm[..idsp_file.sample_count], 48000, 6000, ResampleQuality::WindowedSinc);
+
+        let decoded = decode_yamaha_adpcm(&encode_yamaha_adpcm(&pcm));
+        let error: i64 = decoded.iter().zip(&pcm).map(|(&a, &b)| (a as i64 - b as i64).abs()).sum();
+        let magnitude: i64 = pcm.iter().map(|&sample| (sample as i64).abs()).sum();
+        assert!(error * 4 < magnitude, "error {} against {}", error, magnitude);
+    }
+}
@@ -0,0 +1,96 @@
+/* Exercises the C interface against the IDSP file given as the only argument. */
+
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "idsp.h"
+
+#define CHECK(condition)                                                       \
+  do {                                                                         \
+    if (!(condition)) {                                                        \
+      fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__,         \
+              #condition);                                                     \
+      exit(1);                                                                 \
+    }                                                                          \
+  } while (0)
+
+static uint8_t *read_file(const char *path, size_t *len) {
+  FILE *file = fopen(path, "rb");
+  CHECK(file != NULL);
+
+  fseek(file, 0, SEEK_END);
+  *len = (size_t)ftell(file);
+  fseek(file, 0, SEEK_SET);
+
+  uint8_t *bytes = malloc(*len);
+  CHECK(fread(bytes, 1, *len, file) == *len);
+  fclose(file);
+
+  return bytes;
+}
+
+int main(int argc, char **argv) {
+  CHECK(argc == 2);
+
+  size_t len;
+  uint8_t *bytes = read_file(argv[1], &len);
+
+  IdspContainer *container = NULL;
+  CHECK(idsp_open(bytes, 4, &container) == IDSP_STATUS_INVALID_HEADER);
+  CHECK(idsp_open(bytes, len, &container) == IDSP_STATUS_OK);
+  free(bytes);
+
+  CHECK(idsp_channel_count(container) == 1);
+  CHECK(idsp_sample_count(container) == 47035);
+  CHECK(idsp_sample_rate(container) == 48000);
+
+  size_t loop_start, loop_end;
+  CHECK(!idsp_loop_points(container, &loop_start, &loop_end));
+
+  size_t sample_count = idsp_sample_count(container);
+  int16_t *pcm = malloc(sample_count * 2 * sizeof(int16_t));
+  CHECK(idsp_decode_channel(container, 1, pcm, sample_count) == IDSP_STATUS_INVALID_CHANNEL);
+  CHECK(idsp_decode_channel(container, 0, pcm, sample_count - 1) == IDSP_STATUS_BUFFER_TOO_SMALL);
+  CHECK(idsp_decode_channel(container, 0, pcm, sample_count) == IDSP_STATUS_OK);
+  idsp_free(container);
+
+  /* Encode a looping stereo copy, with the second channel inverted. */
+  int16_t *stereo = malloc(sample_count * 2 * sizeof(int16_t));
+  for (size_t i = 0; i < sample_count; i++) {
+    stereo[i * 2] = pcm[i];
+    stereo[i * 2 + 1] = (int16_t)(pcm[i] == INT16_MIN ? INT16_MAX : -pcm[i]);
+  }
+
+  IdspBuffer encoded;
+  CHECK(idsp_encode(stereo, SIZE_MAX / 2 + 1, 2, 48000, false, 0, 0, &encoded) ==
+        IDSP_STATUS_INVALID_AUDIO_LENGTH);
+  CHECK(idsp_encode(stereo, sample_count, 2, 48000, true, 2000, 1000, &encoded) ==
+        IDSP_STATUS_INVALID_LOOP_POINTS);
+  CHECK(idsp_encode(stereo, sample_count, 2, 48000, true, 1008, 40000, &encoded) ==
+        IDSP_STATUS_OK);
+
+  CHECK(idsp_open(encoded.data, encoded.len, &container) == IDSP_STATUS_OK);
+  idsp_buffer_free(encoded);
+
+  CHECK(idsp_channel_count(container) == 2);
+  CHECK(idsp_sample_count(container) == sample_count);
+  CHECK(idsp_loop_points(container, &loop_start, &loop_end));
+  CHECK(loop_start == 1008 && loop_end == 40000);
+
+  /* Re-encoding is lossy, but should stay close to the original. */
+  int16_t *decoded = malloc(sample_count * sizeof(int16_t));
+  CHECK(idsp_decode_channel(container, 1, decoded, sample_count) == IDSP_STATUS_OK);
+  long total_error = 0;
+  for (size_t i = 0; i < sample_count; i++) {
+    total_error += labs((long)decoded[i] - (long)stereo[i * 2 + 1]);
+  }
+  CHECK(total_error / (long)sample_count < 64);
+
+  idsp_free(container);
+  free(decoded);
+  free(stereo);
+  free(pcm);
+
+  return 0;
+}